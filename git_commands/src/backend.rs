@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+
+use crate::{git, Error};
+
+/// A local branch, as returned by `GitBackend::branches()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchRef {
+    /// Short name, e.g. `master`.
+    pub name: String,
+    /// Short name of the upstream, e.g. `origin/master`.
+    pub upstream: Option<String>,
+    /// Path to the worktree it is checked out in, if any.
+    pub worktree: Option<PathBuf>,
+    /// Author email of the tip commit, without the `<>`.
+    pub author_email: String,
+    /// Author date of the tip commit in seconds since the Unix epoch.
+    pub author_date: i64,
+}
+
+/// Read-only queries and ref updates. These are run a lot (several times for
+/// every branch) so spawning a `git` process for each can be slow, especially
+/// on Windows and network filesystems. The native implementation avoids that.
+///
+/// Anything that touches the worktree (rebasing, switching branches etc.)
+/// still uses the `git` command.
+pub trait GitBackend: Sync {
+    /// The object ID that `rev` refers to, like `git rev-parse`.
+    fn commit_hash(&self, working_dir: &Path, rev: &str) -> Result<String, Error>;
+
+    /// The best common ancestor of `a` and `b`, like `git merge-base`, or
+    /// `None` if they don't have one.
+    fn merge_base(&self, working_dir: &Path, a: &str, b: &str)
+        -> Result<Option<String>, Error>;
+
+    /// The commits reachable from `to` but not `from`, newest first, like
+    /// `git log from..to`.
+    fn commit_list(&self, working_dir: &Path, from: &str, to: &str) -> Result<Vec<String>, Error>;
+
+    /// All branches in `refs/heads`, sorted by name.
+    fn branches(&self, working_dir: &Path) -> Result<Vec<BranchRef>, Error>;
+
+    /// Set the ref `name` (e.g. `refs/heads/master`) to `new`. If `old` is
+    /// given the update only happens if the ref currently points to `old`.
+    fn update_ref(
+        &self,
+        working_dir: &Path,
+        name: &str,
+        new: &str,
+        old: Option<&str>,
+        message: &str,
+    ) -> Result<(), Error>;
+}
+
+/// The backend to use. This is the native one if the `libgit2` feature is
+/// enabled, otherwise it runs `git`.
+pub fn backend() -> &'static dyn GitBackend {
+    #[cfg(feature = "libgit2")]
+    {
+        &crate::native::Libgit2
+    }
+    #[cfg(not(feature = "libgit2"))]
+    {
+        &Subprocess
+    }
+}
+
+/// Implements `GitBackend` by running `git`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Subprocess;
+
+fn stdout_string(stdout: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(stdout).map_err(|_| Error::Parse("git output is not UTF-8".to_owned()))
+}
+
+impl GitBackend for Subprocess {
+    fn commit_hash(&self, working_dir: &Path, rev: &str) -> Result<String, Error> {
+        let output = stdout_string(git(&["rev-parse", rev], working_dir)?.stdout)?;
+        Ok(output.trim().to_owned())
+    }
+
+    fn merge_base(
+        &self,
+        working_dir: &Path,
+        a: &str,
+        b: &str,
+    ) -> Result<Option<String>, Error> {
+        let output = match git(&["merge-base", a, b], working_dir) {
+            Ok(output) => output,
+            // `git merge-base` exits with 1 and no output if there is no
+            // common ancestor; anything else is a real error.
+            Err(Error::Process(e)) if e.output().status.code() == Some(1) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(stdout_string(output.stdout)?.trim().to_owned()))
+    }
+
+    fn commit_list(&self, working_dir: &Path, from: &str, to: &str) -> Result<Vec<String>, Error> {
+        let output = git(
+            &[
+                "--no-pager",
+                "log",
+                "--format=%H",
+                &format!("{}..{}", from, to),
+            ],
+            working_dir,
+        )?
+        .stdout;
+        Ok(stdout_string(output)?
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect())
+    }
+
+    fn branches(&self, working_dir: &Path) -> Result<Vec<BranchRef>, Error> {
+        let output = git(
+            &[
+                "for-each-ref",
+                "--format=%(refname:short)%00%(upstream:short)%00%(worktreepath)%00%(authoremail)%00%(authordate:unix)",
+                "refs/heads",
+            ],
+            working_dir,
+        )?
+        .stdout;
+        let output = stdout_string(output)?;
+
+        output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let parts: Vec<&str> = line.split('\0').collect();
+                if parts.len() != 5 {
+                    return Err(Error::Parse(format!(
+                        "for-each-ref parse error, got {} parts, expected 5",
+                        parts.len()
+                    )));
+                }
+
+                let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_owned());
+
+                Ok(BranchRef {
+                    name: parts[0].to_owned(),
+                    upstream: non_empty(parts[1]),
+                    worktree: non_empty(parts[2]).map(PathBuf::from),
+                    author_email: parts[3]
+                        .strip_prefix('<')
+                        .and_then(|e| e.strip_suffix('>'))
+                        .unwrap_or(parts[3])
+                        .to_owned(),
+                    author_date: parts[4].parse().map_err(|_| {
+                        Error::Parse(format!("invalid author date: {:?}", parts[4]))
+                    })?,
+                })
+            })
+            .collect()
+    }
+
+    fn update_ref(
+        &self,
+        working_dir: &Path,
+        name: &str,
+        new: &str,
+        old: Option<&str>,
+        message: &str,
+    ) -> Result<(), Error> {
+        let mut args = vec!["update-ref", "-m", message, name, new];
+        args.extend(old);
+        git(&args, working_dir)?;
+        Ok(())
+    }
+}
@@ -4,8 +4,23 @@ use std::{
     fmt, io,
     path::Path,
     process::{self, Command},
+    time::{Duration, Instant, SystemTime},
 };
 
+mod backend;
+pub use backend::*;
+#[cfg(feature = "libgit2")]
+mod native;
+#[cfg(feature = "libgit2")]
+pub use native::Libgit2;
+mod settings;
+pub use settings::{
+    configure, interactive, is_cancelled, is_interactive, uncancellable, CancellationToken,
+    Settings,
+};
+mod trace;
+pub use trace::{read_trace, TraceFile, TraceRecord, REDACTED};
+
 // Define our error types. These may be customized for our error handling cases.
 // Now we will be able to write our own errors, defer to an underlying error
 // implementation, or do something in between.
@@ -13,18 +28,142 @@ use std::{
 pub enum Error {
     Io(io::Error),
     Process(ProcessError),
+    /// Git's output wasn't what we expected.
+    Parse(String),
+    /// The command was killed because it took too long.
+    TimedOut {
+        command: Vec<String>,
+        timeout: Duration,
+    },
+    /// The command was killed or not run because the `CancellationToken`
+    /// was cancelled.
+    Cancelled,
+    #[cfg(feature = "libgit2")]
+    Native(git2::Error),
 }
 
 #[derive(Debug)]
 pub struct ProcessError {
     output: process::Output,
     command: Vec<String>,
+    kind: ErrorKind,
+}
+
+impl ProcessError {
+    /// What went wrong, according to git's output.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn output(&self) -> &process::Output {
+        &self.output
+    }
+}
+
+/// Common reasons for git commands to fail. Most of these are environmental
+/// problems that have nothing to do with the commits involved, so they
+/// shouldn't be treated the same as conflicts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A merge, rebase, cherry-pick etc. stopped due to conflicts.
+    MergeConflict,
+    /// The index is locked (`index.lock` exists), probably because another
+    /// git process is running or crashed.
+    IndexLocked,
+    /// Uncommitted changes in the worktree would be overwritten.
+    WouldOverwrite,
+    /// A ref couldn't be locked for updating.
+    RefLocked,
+    /// A ref wasn't updated because it no longer points to the commit it was
+    /// expected to, or has been deleted.
+    RefChanged,
+    /// A revision or ref doesn't exist.
+    UnknownRevision,
+    /// Signing a commit failed.
+    SigningFailed,
+    /// The command took longer than the configured timeout.
+    TimedOut,
+    /// The command was cancelled.
+    Cancelled,
+    /// Anything else.
+    Other,
+}
+
+impl ErrorKind {
+    /// Work out what went wrong from a failed command's output. Commands are
+    /// run with `LC_MESSAGES=C` (see `Settings`) so the messages aren't
+    /// translated.
+    fn classify(stdout: &str, stderr: &str) -> Self {
+        // Only look at git's own messages, not everything it prints, because
+        // the output also contains commit subjects, which could say anything.
+        // Conflict messages include the subject too, so that part is cut off.
+        // Progress messages end in `\r` rather than a newline.
+        let messages: Vec<&str> = stdout
+            .split(['\n', '\r'])
+            .chain(stderr.split(['\n', '\r']))
+            .filter_map(|line| {
+                let prefixes = [
+                    "error: could not apply",
+                    "Could not apply",
+                    "could not apply",
+                    "CONFLICT (",
+                ];
+                if let Some(prefix) = prefixes.iter().find(|&&p| line.starts_with(p)) {
+                    Some(*prefix)
+                } else if line.starts_with("error:") || line.starts_with("fatal:") {
+                    Some(line)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let has = |pattern: &str| messages.iter().any(|message| message.contains(pattern));
+
+        // Check these first; they can cause a rebase to stop, which looks like
+        // a conflict.
+        if has("failed to sign") {
+            Self::SigningFailed
+        } else if has("index.lock': File exists") {
+            Self::IndexLocked
+        } else if has("but expected")
+            || (has("update_ref failed for ref") && has("unable to resolve reference"))
+        {
+            // The second is an update of a ref that was expected to exist.
+            Self::RefChanged
+        } else if has("cannot lock ref") || has(".lock': File exists") {
+            Self::RefLocked
+        } else if has("would be overwritten by")
+            || has("You have unstaged changes")
+            || has("Your index contains uncommitted changes")
+        {
+            Self::WouldOverwrite
+        } else if has("CONFLICT (") || has("Could not apply") || has("could not apply") {
+            Self::MergeConflict
+        } else if has("unknown revision")
+            || has("bad revision")
+            || has("Not a valid object name")
+            || has("not a valid object name")
+            || has("invalid upstream")
+            || has("Needed a single revision")
+        {
+            Self::UnknownRevision
+        } else {
+            Self::Other
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self {
             Self::Io(e) => e.fmt(f),
+            Self::Parse(e) => e.fmt(f),
+            Self::TimedOut { command, timeout } => {
+                write!(f, "{:?} timed out after {:?}", command, timeout)
+            }
+            Self::Cancelled => f.write_str("cancelled"),
+            #[cfg(feature = "libgit2")]
+            Self::Native(e) => e.fmt(f),
             Self::Process(e) => {
                 write!(
                     f,
@@ -43,6 +182,20 @@ impl fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// What went wrong. I/O errors (e.g. `git` not being installed) are `Other`.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Process(e) => e.kind(),
+            #[cfg(feature = "libgit2")]
+            Self::Native(e) => native::error_kind(e),
+            Self::TimedOut { .. } => ErrorKind::TimedOut,
+            Self::Cancelled => ErrorKind::Cancelled,
+            Self::Io(_) | Self::Parse(_) => ErrorKind::Other,
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         Self::Io(e)
@@ -53,18 +206,65 @@ impl std::error::Error for Error {}
 
 /// Run a git command with the given arguments in the current directory.
 pub fn git_cwd(args: &[&str]) -> Result<process::Output, Error> {
-    git_internal(args, None)
+    git_internal(args, None, &[])
 }
 
 /// Run a git command with the given arguments in the given directory.
 pub fn git(args: &[&str], working_dir: &Path) -> Result<process::Output, Error> {
-    git_internal(args, Some(working_dir))
+    git_internal(args, Some(working_dir), &[])
+}
+
+/// Run a git command with the given arguments and additional environment
+/// variables in the given directory.
+pub fn git_with_env(
+    args: &[&str],
+    working_dir: &Path,
+    env: &[(&str, &str)],
+) -> Result<process::Output, Error> {
+    git_internal(args, Some(working_dir), env)
+}
+
+/// Like `git_with_env()` but `progress` is called with each line of stderr as
+/// it is written, e.g. `Rebasing (3/12)` or `Receiving objects:  45% (9/20)`.
+/// Note that git only writes some progress messages if stderr is a terminal
+/// or `--progress` is given.
+pub fn git_with_progress(
+    args: &[&str],
+    working_dir: &Path,
+    env: &[(&str, &str)],
+    progress: &mut dyn FnMut(&str),
+) -> Result<process::Output, Error> {
+    run_git(args, Some(working_dir), env, Some(progress))
+}
+
+/// Run a command that isn't git (e.g. a user's script) with the configured
+/// timeout and cancellation. Unlike git commands its environment and stdin
+/// are left alone, and it isn't an error for it to fail.
+pub fn run_command(command: &mut Command) -> Result<process::Output, Error> {
+    debug!("$ {:?}", command);
+    settings::settings().run(command, None)
+}
+
+pub fn git_internal(
+    args: &[&str],
+    working_dir: Option<&Path>,
+    env: &[(&str, &str)],
+) -> Result<process::Output, Error> {
+    run_git(args, working_dir, env, None)
 }
 
-pub fn git_internal(args: &[&str], working_dir: Option<&Path>) -> Result<process::Output, Error> {
+fn run_git(
+    args: &[&str],
+    working_dir: Option<&Path>,
+    env: &[(&str, &str)],
+    progress: Option<&mut dyn FnMut(&str)>,
+) -> Result<process::Output, Error> {
     debug!(
-        "{} $ {} {}",
+        "{} $ {}{} {}",
         working_dir.unwrap_or(Path::new("")).to_string_lossy(),
+        env.iter()
+            .map(|(k, v)| format!("{}={} ", k, v))
+            .collect::<String>(),
         "git".bold(),
         args.join(" ").bold()
     );
@@ -73,13 +273,37 @@ pub fn git_internal(args: &[&str], working_dir: Option<&Path>) -> Result<process
     if let Some(working_dir) = working_dir {
         command.current_dir(working_dir);
     }
+    let settings = settings::settings();
+    settings.apply(&mut command);
+    command.envs(env.iter().copied());
+    command.args(args);
 
-    let output = command.args(args).output()?;
+    let start = (SystemTime::now(), Instant::now());
+    let result = settings.run(&mut command, progress);
+
+    if let Some(trace) = &settings.trace {
+        trace.write(&TraceRecord::new(
+            start.0,
+            working_dir,
+            args,
+            &settings.env,
+            env,
+            start.1.elapsed(),
+            &result,
+        ));
+    }
+
+    let output = result?;
 
     debug!("{:?}", output);
 
     if !output.status.success() {
+        let kind = ErrorKind::classify(
+            &String::from_utf8_lossy(&output.stdout),
+            &String::from_utf8_lossy(&output.stderr),
+        );
         return Err(Error::Process(ProcessError {
+            kind,
             output,
             command: std::iter::once(&"git")
                 .chain(args.iter())
@@ -90,3 +314,90 @@ pub fn git_internal(args: &[&str], working_dir: Option<&Path>) -> Result<process
 
     Ok(output)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classify_errors() {
+        let cases = [
+            (
+                "",
+                "error: could not apply 1234567... WIP\nhint: Resolve all conflicts manually",
+                ErrorKind::MergeConflict,
+            ),
+            (
+                "Auto-merging a.txt\nCONFLICT (content): Merge conflict in a.txt\n",
+                "",
+                ErrorKind::MergeConflict,
+            ),
+            (
+                "",
+                "fatal: Unable to create '/repo/.git/index.lock': File exists.\n",
+                ErrorKind::IndexLocked,
+            ),
+            (
+                "",
+                "error: Your local changes to the following files would be overwritten by checkout:\n\ta.txt\n",
+                ErrorKind::WouldOverwrite,
+            ),
+            (
+                "",
+                "error: cannot rebase: You have unstaged changes.\n",
+                ErrorKind::WouldOverwrite,
+            ),
+            (
+                "",
+                "error: update_ref failed for ref 'refs/heads/wip': cannot lock ref 'refs/heads/wip': Unable to create '/repo/.git/refs/heads/wip.lock': File exists.\n",
+                ErrorKind::RefLocked,
+            ),
+            (
+                "",
+                "fatal: update_ref failed for ref 'refs/heads/wip': cannot lock ref 'refs/heads/wip': is at 55b1ed65c7e86ec34c1fcdc8035607f5b33d0604 but expected 0f88e908bbcb5efe3bd1e522c60550143a62cb3e\n",
+                ErrorKind::RefChanged,
+            ),
+            (
+                "",
+                "fatal: update_ref failed for ref 'refs/heads/wip': cannot lock ref 'refs/heads/wip': unable to resolve reference 'refs/heads/wip'\n",
+                ErrorKind::RefChanged,
+            ),
+            (
+                "",
+                "fatal: unable to resolve reference 'refs/heads/wip': reference broken\n",
+                ErrorKind::Other,
+            ),
+            (
+                "",
+                "fatal: ambiguous argument 'nope': unknown revision or path not in the working tree.\n",
+                ErrorKind::UnknownRevision,
+            ),
+            ("", "fatal: invalid upstream 'nope'\n", ErrorKind::UnknownRevision),
+            (
+                "",
+                "error: gpg failed to sign the data\nfatal: failed to write commit object\n",
+                ErrorKind::SigningFailed,
+            ),
+            ("", "fatal: something else\n", ErrorKind::Other),
+            // Commit subjects could contain any of the messages above.
+            (
+                "",
+                "Rebasing (1/2)\rerror: could not apply 1234567... Return zero but expected one\nhint: Resolve all conflicts manually",
+                ErrorKind::MergeConflict,
+            ),
+            (
+                "[detached HEAD 1234567] Fix index.lock': File exists\nCONFLICT (modify/delete): a.txt deleted in HEAD and modified in 89abcde (Retry when failed to sign).\n",
+                "",
+                ErrorKind::MergeConflict,
+            ),
+            (
+                "[detached HEAD 1234567] Handle unknown revision\n",
+                "fatal: something else\n",
+                ErrorKind::Other,
+            ),
+        ];
+        for (stdout, stderr, kind) in cases {
+            assert_eq!(ErrorKind::classify(stdout, stderr), kind, "{}", stderr);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use git2::{BranchType, ErrorClass, ErrorCode, Oid, Repository, Sort};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{BranchRef, Error, ErrorKind, GitBackend};
+
+/// Implements `GitBackend` using libgit2, without spawning any processes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Libgit2;
+
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Self {
+        Self::Native(e)
+    }
+}
+
+pub(crate) fn error_kind(e: &git2::Error) -> ErrorKind {
+    match (e.code(), e.class()) {
+        (ErrorCode::Locked, ErrorClass::Index) => ErrorKind::IndexLocked,
+        (ErrorCode::Locked, _) => ErrorKind::RefLocked,
+        // E.g. `reference_matching()` when the ref has moved.
+        (ErrorCode::Modified, _) => ErrorKind::RefChanged,
+        (ErrorCode::NotFound | ErrorCode::InvalidSpec | ErrorCode::Ambiguous, _) => {
+            ErrorKind::UnknownRevision
+        }
+        (ErrorCode::Conflict | ErrorCode::MergeConflict, _) => ErrorKind::MergeConflict,
+        _ => ErrorKind::Other,
+    }
+}
+
+fn open(working_dir: &Path) -> Result<Repository, Error> {
+    Ok(Repository::discover(working_dir)?)
+}
+
+fn commit_id(repo: &Repository, rev: &str) -> Result<Oid, Error> {
+    Ok(repo.revparse_single(rev)?.peel_to_commit()?.id())
+}
+
+/// `refs/heads/foo` -> `foo`, `refs/remotes/origin/foo` -> `origin/foo`.
+fn short_name(refname: &str) -> &str {
+    refname
+        .strip_prefix("refs/heads/")
+        .or_else(|| refname.strip_prefix("refs/remotes/"))
+        .unwrap_or(refname)
+}
+
+/// Remove any trailing slash so paths match the ones `git` prints.
+fn normalise_path(path: &Path) -> PathBuf {
+    path.components().collect()
+}
+
+/// The branch that HEAD points to in `repo`, if any.
+fn head_ref(repo: &Repository) -> Option<String> {
+    repo.find_reference("HEAD")
+        .ok()?
+        .symbolic_target()
+        .map(ToOwned::to_owned)
+}
+
+/// Map from ref name (`refs/heads/...`) to the worktree it is checked out in.
+fn checked_out_branches(repo: &Repository) -> Result<HashMap<String, PathBuf>, Error> {
+    // Start from the main worktree even if we were opened from a linked one.
+    let main = Repository::open(repo.commondir())?;
+
+    let mut branches = HashMap::new();
+
+    if let (Some(head), Some(workdir)) = (head_ref(&main), main.workdir()) {
+        branches.insert(head, normalise_path(workdir));
+    }
+
+    for name in main.worktrees()?.iter().flatten() {
+        let worktree = main.find_worktree(name)?;
+        // Skip worktrees that have been deleted but not pruned.
+        let Ok(worktree_repo) = Repository::open_from_worktree(&worktree) else {
+            continue;
+        };
+        if let Some(head) = head_ref(&worktree_repo) {
+            branches.insert(head, normalise_path(worktree.path()));
+        }
+    }
+
+    Ok(branches)
+}
+
+impl GitBackend for Libgit2 {
+    fn commit_hash(&self, working_dir: &Path, rev: &str) -> Result<String, Error> {
+        let repo = open(working_dir)?;
+        let id = repo.revparse_single(rev)?.id();
+        Ok(id.to_string())
+    }
+
+    fn merge_base(
+        &self,
+        working_dir: &Path,
+        a: &str,
+        b: &str,
+    ) -> Result<Option<String>, Error> {
+        let repo = open(working_dir)?;
+        let a = commit_id(&repo, a)?;
+        let b = commit_id(&repo, b)?;
+        match repo.merge_base(a, b) {
+            Ok(id) => Ok(Some(id.to_string())),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn commit_list(&self, working_dir: &Path, from: &str, to: &str) -> Result<Vec<String>, Error> {
+        let repo = open(working_dir)?;
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        walk.push(commit_id(&repo, to)?)?;
+        walk.hide(commit_id(&repo, from)?)?;
+        walk.map(|id| Ok(id?.to_string())).collect()
+    }
+
+    fn branches(&self, working_dir: &Path) -> Result<Vec<BranchRef>, Error> {
+        let repo = open(working_dir)?;
+        let mut checked_out = checked_out_branches(&repo)?;
+
+        let mut branches = repo
+            .branches(Some(BranchType::Local))?
+            .map(|branch| {
+                let (branch, _) = branch?;
+                let reference = branch.get();
+                let refname = reference
+                    .name()
+                    .ok_or_else(|| Error::Parse("branch name is not UTF-8".to_owned()))?
+                    .to_owned();
+
+                let upstream = match repo.branch_upstream_name(&refname) {
+                    Ok(upstream) => Some(
+                        upstream
+                            .as_str()
+                            .map(|u| short_name(u).to_owned())
+                            .ok_or_else(|| Error::Parse("upstream is not UTF-8".to_owned()))?,
+                    ),
+                    Err(e) if e.code() == ErrorCode::NotFound => None,
+                    Err(e) => return Err(e.into()),
+                };
+
+                let commit = reference.peel_to_commit()?;
+                let author = commit.author();
+
+                Ok(BranchRef {
+                    name: short_name(&refname).to_owned(),
+                    upstream,
+                    worktree: checked_out.remove(&refname),
+                    author_email: String::from_utf8_lossy(author.email_bytes()).into_owned(),
+                    author_date: author.when().seconds(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        branches.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(branches)
+    }
+
+    fn update_ref(
+        &self,
+        working_dir: &Path,
+        name: &str,
+        new: &str,
+        old: Option<&str>,
+        message: &str,
+    ) -> Result<(), Error> {
+        let repo = open(working_dir)?;
+        let new = Oid::from_str(new)?;
+        match old {
+            Some(old) => repo.reference_matching(name, new, true, Oid::from_str(old)?, message)?,
+            None => repo.reference(name, new, true, message)?,
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    cell::Cell,
+    io::Read,
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, PoisonError, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Error, TraceFile};
+
+/// Lets one thread (e.g. a Ctrl-C handler) cancel the git commands running
+/// on the others.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Kill any running commands and make new ones fail immediately.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Settings that apply to every git command.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Kill commands that haven't finished after this long.
+    pub timeout: Option<Duration>,
+    /// Environment variables to set for every command. These override the
+    /// non-interactive defaults.
+    pub env: Vec<(String, String)>,
+    /// When this is cancelled running commands are killed and new ones fail.
+    pub cancellation: Option<CancellationToken>,
+    /// Record every command here.
+    pub trace: Option<TraceFile>,
+}
+
+static SETTINGS: RwLock<Settings> = RwLock::new(Settings {
+    timeout: None,
+    env: Vec::new(),
+    cancellation: None,
+    trace: None,
+});
+
+thread_local! {
+    static UNCANCELLABLE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set while the user is in control of an interactive command.
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Set the settings for all subsequent git commands.
+pub fn configure(settings: Settings) {
+    *SETTINGS.write().unwrap_or_else(PoisonError::into_inner) = settings;
+}
+
+pub(crate) fn settings() -> Settings {
+    SETTINGS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// Has the configured cancellation token been cancelled?
+pub fn is_cancelled() -> bool {
+    settings()
+        .cancellation
+        .is_some_and(|token| token.is_cancelled())
+}
+
+/// Run `f` with cancellation ignored on this thread. This is for cleaning up
+/// after a command was cancelled.
+pub fn uncancellable<T>(f: impl FnOnce() -> T) -> T {
+    let previous = UNCANCELLABLE.with(|u| u.replace(true));
+    let result = f();
+    UNCANCELLABLE.with(|u| u.set(previous));
+    result
+}
+
+/// Run `f`, which hands the terminal over to the user (e.g. to run a shell),
+/// during which Ctrl-C is meant for them rather than us. See
+/// `is_interactive()`.
+pub fn interactive<T>(f: impl FnOnce() -> T) -> T {
+    let previous = INTERACTIVE.swap(true, Ordering::SeqCst);
+    let result = f();
+    INTERACTIVE.store(previous, Ordering::SeqCst);
+    result
+}
+
+/// Is an `interactive()` command running? Ctrl-C handlers should ignore
+/// Ctrl-C while it is, since the command gets it too.
+pub fn is_interactive() -> bool {
+    INTERACTIVE.load(Ordering::SeqCst)
+}
+
+/// Set for every command so that git never waits for input, since there may
+/// be nobody to provide it.
+const NON_INTERACTIVE_ENV: &[(&str, &str)] = &[
+    // We look at the output to classify errors so it mustn't be translated.
+    // Only messages are affected; things like the encoding of file names
+    // still follow the user's locale. `LANGUAGE` takes priority over the
+    // user's `LC_ALL` for messages, and `LC_MESSAGES` applies if they don't
+    // set `LC_ALL` (when `LANGUAGE` is ignored).
+    ("LANGUAGE", "C"),
+    ("LC_MESSAGES", "C"),
+    // Fail instead of prompting for credentials.
+    ("GIT_TERMINAL_PROMPT", "0"),
+    // Don't open an editor for commit messages etc.
+    ("GIT_EDITOR", ":"),
+];
+
+impl Settings {
+    /// Set up the environment and stdin of `command`.
+    pub(crate) fn apply(&self, command: &mut Command) {
+        command.envs(NON_INTERACTIVE_ENV.iter().copied());
+        command.envs(self.env.iter().map(|(k, v)| (k, v)));
+        // Hooks that read stdin get EOF instead of hanging.
+        command.stdin(Stdio::null());
+    }
+
+    /// Run `command`, killing it if it times out or is cancelled. If
+    /// `progress` is given it is called with each line of stderr as it is
+    /// written. Lines may end with `\r` or `\n` since git uses `\r` to
+    /// overwrite progress lines.
+    pub(crate) fn run(
+        &self,
+        command: &mut Command,
+        mut progress: Option<&mut dyn FnMut(&str)>,
+    ) -> Result<Output, Error> {
+        let cancellation = self
+            .cancellation
+            .as_ref()
+            .filter(|_| !UNCANCELLABLE.with(Cell::get));
+        let cancelled = || cancellation.is_some_and(|token| token.is_cancelled());
+
+        if cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        if self.timeout.is_none() && cancellation.is_none() && progress.is_none() {
+            return Ok(command.output()?);
+        }
+
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Read the output in the background so the pipes don't fill up.
+        let read_all = |mut pipe: Box<dyn Read + Send>| {
+            thread::spawn(move || {
+                let mut buffer = Vec::new();
+                let _ = pipe.read_to_end(&mut buffer);
+                buffer
+            })
+        };
+        let stdout = read_all(Box::new(child.stdout.take().expect("stdout is piped")));
+        let stderr_pipe = child.stderr.take().expect("stderr is piped");
+        let (stderr, progress_lines) = if progress.is_some() {
+            let (sender, receiver) = mpsc::channel();
+            (read_lines(stderr_pipe, sender), Some(receiver))
+        } else {
+            (read_all(Box::new(stderr_pipe)), None)
+        };
+
+        // Pass any progress lines we have received to the callback, waiting
+        // up to `timeout` for the first one.
+        let mut report_progress = |timeout: Duration| match (&mut progress, &progress_lines) {
+            (Some(progress), Some(lines)) => match lines.recv_timeout(timeout) {
+                Ok(line) => {
+                    progress(&line);
+                    lines.try_iter().for_each(|line| progress(&line));
+                }
+                // Stderr has been closed but it hasn't exited yet.
+                Err(mpsc::RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+            },
+            _ => thread::sleep(timeout),
+        };
+
+        let start = Instant::now();
+        // Start polling quickly because most commands are fast.
+        let mut poll_interval = Duration::from_millis(1);
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            let timed_out = self.timeout.is_some_and(|t| start.elapsed() >= t);
+            if timed_out || cancelled() {
+                // It may have exited by itself in the meantime, so ignore errors.
+                let _ = child.kill();
+                let _ = child.wait();
+                // Don't wait for the output; anything it started may still
+                // have the pipes open.
+                return Err(if timed_out {
+                    Error::TimedOut {
+                        command: std::iter::once(command.get_program())
+                            .chain(command.get_args())
+                            .map(|arg| arg.to_string_lossy().into_owned())
+                            .collect(),
+                        timeout: self.timeout.unwrap_or_default(),
+                    }
+                } else {
+                    Error::Cancelled
+                });
+            }
+
+            report_progress(poll_interval);
+            poll_interval = (poll_interval * 2).min(Duration::from_millis(50));
+        };
+
+        let stderr = stderr.join().unwrap_or_default();
+        // Anything that was written just before it exited.
+        report_progress(Duration::ZERO);
+
+        // Ctrl-C goes to git as well, so it may have been killed before
+        // we noticed.
+        if !status.success() && cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr,
+        })
+    }
+}
+
+/// Read all of `pipe` in the background, sending each line to `lines` as it
+/// arrives (without the line ending), and return everything that was read.
+fn read_lines(
+    mut pipe: impl Read + Send + 'static,
+    lines: mpsc::Sender<String>,
+) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let mut line_start = 0;
+        let mut chunk = [0; 4096];
+        while let Ok(n @ 1..) = pipe.read(&mut chunk) {
+            buffer.extend_from_slice(&chunk[..n]);
+            while let Some(end) = buffer[line_start..]
+                .iter()
+                .position(|&c| c == b'\r' || c == b'\n')
+            {
+                let line = String::from_utf8_lossy(&buffer[line_start..line_start + end]);
+                if !line.trim().is_empty() {
+                    // The receiver may have gone if the command was killed.
+                    let _ = lines.send(line.into_owned());
+                }
+                line_start += end + 1;
+            }
+        }
+        buffer
+    })
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    fn git(settings: &Settings, args: &[&str]) -> Result<Output, Error> {
+        let mut command = Command::new("git");
+        settings.apply(&mut command);
+        command.args(args);
+        settings.run(&mut command, None)
+    }
+
+    const HANG: &[&str] = &["-c", "alias.hang=!sleep 10", "hang"];
+
+    #[test]
+    fn timeout() {
+        let settings = Settings {
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        match git(&settings, HANG) {
+            Err(Error::TimedOut { command, .. }) => assert_eq!(command[0], "git"),
+            result => panic!("expected a timeout, got {:?}", result),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert!(git(&settings, &["version"]).is_ok());
+    }
+
+    #[test]
+    fn timeout_other_command() {
+        let settings = Settings {
+            timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        match settings.run(Command::new("sleep").arg("10"), None) {
+            Err(Error::TimedOut { command, .. }) => assert_eq!(command, ["sleep", "10"]),
+            result => panic!("expected a timeout, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn cancel() {
+        let token = CancellationToken::new();
+        let settings = Settings {
+            cancellation: Some(token.clone()),
+            ..Default::default()
+        };
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            token.cancel();
+        });
+        assert!(matches!(git(&settings, HANG), Err(Error::Cancelled)));
+        canceller.join().unwrap();
+
+        // New commands fail immediately, unless they are cleaning up.
+        assert!(matches!(
+            git(&settings, &["version"]),
+            Err(Error::Cancelled)
+        ));
+        assert!(uncancellable(|| git(&settings, &["version"])).is_ok());
+    }
+
+    #[test]
+    fn interactive_commands() {
+        assert!(!is_interactive());
+        assert!(interactive(is_interactive));
+        assert!(!is_interactive());
+    }
+
+    #[test]
+    fn progress() {
+        let mut lines = Vec::new();
+        let args = [
+            "-c",
+            "alias.progress=!printf 'one\\rtwo\\n\\nthree' >&2",
+            "progress",
+        ];
+        let mut command = Command::new("git");
+        command.args(args);
+        let output = Settings::default()
+            .run(&mut command, Some(&mut |line| lines.push(line.to_owned())))
+            .unwrap();
+        // The last line is incomplete so it isn't reported.
+        assert_eq!(lines, ["one", "two"]);
+        assert_eq!(output.stderr, b"one\rtwo\n\nthree");
+    }
+
+    #[test]
+    fn environment() {
+        let editor = |settings: &Settings| {
+            let output = git(settings, &["var", "GIT_EDITOR"]).unwrap();
+            String::from_utf8(output.stdout).unwrap().trim().to_owned()
+        };
+        assert_eq!(editor(&Settings::default()), ":");
+        assert_eq!(
+            editor(&Settings {
+                env: vec![("GIT_EDITOR".to_owned(), "vi".to_owned())],
+                ..Default::default()
+            }),
+            "vi"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::Error;
+
+/// Output longer than this is truncated in traces.
+const MAX_TRACED_OUTPUT: usize = 4096;
+
+/// Recorded instead of the values of environment variables from
+/// `Settings::env` (`--git-env`), because they may be secret.
+pub const REDACTED: &str = "<redacted>";
+
+/// One git command in a trace file. Trace files have one of these per line,
+/// as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// When it started, in milliseconds since the Unix epoch.
+    pub start_ms: u64,
+    /// The directory it was run in, if not the current one.
+    pub cwd: Option<PathBuf>,
+    /// Arguments, not including `git`.
+    pub args: Vec<String>,
+    /// Environment variables that were set for it, apart from the
+    /// non-interactive defaults that are set for every command. The values
+    /// of ones from `Settings::env` are `REDACTED`.
+    pub env: Vec<(String, String)>,
+    pub duration_ms: u64,
+    /// `None` if it was killed by a signal or didn't run at all.
+    pub exit_code: Option<i32>,
+    /// Why it didn't run to completion, e.g. it timed out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Possibly truncated.
+    pub stdout: String,
+    /// Possibly truncated.
+    pub stderr: String,
+}
+
+impl TraceRecord {
+    pub(crate) fn new(
+        start: SystemTime,
+        cwd: Option<&Path>,
+        args: &[&str],
+        settings_env: &[(String, String)],
+        env: &[(&str, &str)],
+        duration: Duration,
+        result: &Result<process::Output, Error>,
+    ) -> Self {
+        let (exit_code, error, stdout, stderr) = match result {
+            Ok(output) => (
+                output.status.code(),
+                None,
+                truncate_output(&output.stdout),
+                truncate_output(&output.stderr),
+            ),
+            Err(e) => (None, Some(e.to_string()), String::new(), String::new()),
+        };
+        Self {
+            start_ms: start
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            cwd: cwd.map(ToOwned::to_owned),
+            args: args.iter().map(|&a| a.to_owned()).collect(),
+            env: settings_env
+                .iter()
+                .map(|(k, _)| (k.clone(), REDACTED.to_owned()))
+                .chain(env.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())))
+                .collect(),
+            duration_ms: duration.as_millis() as u64,
+            exit_code,
+            error,
+            stdout,
+            stderr,
+        }
+    }
+
+    /// Did the command run and exit successfully?
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && self.exit_code == Some(0)
+    }
+}
+
+fn truncate_output(output: &[u8]) -> String {
+    if output.len() <= MAX_TRACED_OUTPUT {
+        return String::from_utf8_lossy(output).into_owned();
+    }
+    format!(
+        "{}... [{} bytes truncated]",
+        String::from_utf8_lossy(&output[..MAX_TRACED_OUTPUT]),
+        output.len() - MAX_TRACED_OUTPUT
+    )
+}
+
+/// A file that `TraceRecord`s are appended to.
+#[derive(Clone)]
+pub struct TraceFile {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl fmt::Debug for TraceFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TraceFile").field(&self.path).finish()
+    }
+}
+
+impl TraceFile {
+    /// Create the file, overwriting it if it exists.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            file: Arc::new(Mutex::new(File::create(path)?)),
+        })
+    }
+
+    /// Append a record. Failures are logged rather than returned because
+    /// tracing shouldn't stop anything working.
+    pub(crate) fn write(&self, record: &TraceRecord) {
+        let result = serde_json::to_string(record)
+            .map_err(io::Error::other)
+            .and_then(|line| {
+                // Write whole lines so the file is still readable if we crash.
+                self.file
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .write_all(format!("{}\n", line).as_bytes())
+            });
+        if let Err(e) = result {
+            warn!("Couldn't write to trace file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Read a trace file written by `TraceFile`.
+pub fn read_trace(path: &Path) -> Result<Vec<TraceRecord>, Error> {
+    fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| Error::Parse(format!("trace line {}: {}", number + 1, e)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trace_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let trace = TraceFile::create(&path).unwrap();
+
+        let records = [
+            TraceRecord {
+                start_ms: 1,
+                cwd: Some(dir.path().to_owned()),
+                args: vec!["status".to_owned()],
+                env: vec![("A".to_owned(), "B".to_owned())],
+                duration_ms: 2,
+                exit_code: Some(0),
+                error: None,
+                stdout: "clean\n".to_owned(),
+                stderr: String::new(),
+            },
+            TraceRecord {
+                start_ms: 3,
+                cwd: None,
+                args: vec!["pull".to_owned()],
+                env: Vec::new(),
+                duration_ms: 4,
+                exit_code: None,
+                error: Some("timed out".to_owned()),
+                stdout: String::new(),
+                stderr: String::new(),
+            },
+        ];
+        for record in &records {
+            trace.write(record);
+        }
+
+        assert_eq!(read_trace(&path).unwrap(), records);
+        assert!(records[0].succeeded());
+        assert!(!records[1].succeeded());
+    }
+
+    #[test]
+    fn redacted_env() {
+        let record = TraceRecord::new(
+            UNIX_EPOCH,
+            None,
+            &["fetch"],
+            &[("GITHUB_TOKEN".to_owned(), "secret".to_owned())],
+            &[("GIT_REFLOG_ACTION", "autorebase")],
+            Duration::ZERO,
+            &Err(Error::Cancelled),
+        );
+        assert_eq!(
+            record.env,
+            [
+                ("GITHUB_TOKEN".to_owned(), REDACTED.to_owned()),
+                ("GIT_REFLOG_ACTION".to_owned(), "autorebase".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate_output(b"short"), "short");
+        let long = vec![b'a'; MAX_TRACED_OUTPUT + 10];
+        assert!(truncate_output(&long).ends_with("... [10 bytes truncated]"));
+    }
+}
@@ -1,7 +1,7 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use anyhow::Result;
-use git_commands::git;
+use git_commands::{git, Error};
 
 use crate::trim::TrimAsciiWhitespace;
 
@@ -24,3 +24,120 @@ pub fn default_branch_name(for_path: &Path) -> Result<String> {
     let output = std::str::from_utf8(output.trim_ascii_whitespace())?;
     Ok(output.to_owned())
 }
+
+/// Get the `user.email` git config setting, if it is set.
+pub fn user_email(for_path: &Path) -> Result<Option<String>> {
+    let output = git(
+        &[
+            "--no-pager",
+            "config",
+            "--default",
+            "",
+            "--get",
+            "user.email",
+        ],
+        for_path,
+    )?
+    .stdout;
+    let output = std::str::from_utf8(output.trim_ascii_whitespace())?;
+    Ok(if output.is_empty() {
+        None
+    } else {
+        Some(output.to_owned())
+    })
+}
+
+/// Per-branch settings from git config (`branch.<name>.*`).
+#[derive(Debug, Default, Clone)]
+pub struct BranchConfig {
+    /// `branch.<name>.autorebase`. `false` means never rebase the branch and
+    /// `true` means rebase it even if it has an upstream.
+    pub autorebase: Option<bool>,
+    /// `branch.<name>.autorebaseOnto`: a branch to rebase it onto instead of
+    /// the target branch.
+    pub onto: Option<String>,
+    /// `branch.<name>.description`, as set by `git branch --edit-description`.
+    pub description: Option<String>,
+    /// If one of the settings is invalid, what is wrong with it. The branch
+    /// shouldn't be rebased.
+    pub error: Option<String>,
+}
+
+/// Get the settings for every branch that has any, in one go.
+pub fn branch_configs(for_path: &Path) -> Result<HashMap<String, BranchConfig>> {
+    let output = match git(
+        &[
+            "--no-pager",
+            "config",
+            "--null",
+            "--get-regexp",
+            r"^branch\..*\.(autorebase|autorebaseonto|description)$",
+        ],
+        for_path,
+    ) {
+        Ok(output) => output.stdout,
+        // No matches.
+        Err(Error::Process(e)) if e.output().status.code() == Some(1) => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let output = String::from_utf8(output)?;
+
+    let mut configs: HashMap<String, BranchConfig> = HashMap::new();
+    // Each entry is `<key>\n<value>\0`, or just `<key>\0` if there is no `=`
+    // (which means true for booleans). The section and variable names are
+    // lowercase but branch names keep their case. They can contain dots.
+    for entry in output.split_terminator('\0') {
+        let (key, value) = match entry.split_once('\n') {
+            Some((key, value)) => (key, Some(value)),
+            None => (entry, None),
+        };
+        let Some((branch, variable)) = key
+            .strip_prefix("branch.")
+            .and_then(|key| key.rsplit_once('.'))
+        else {
+            continue;
+        };
+        let config = configs.entry(branch.to_owned()).or_default();
+        // Later values override earlier ones, like `git config --get`.
+        let value_str = value.unwrap_or_default();
+        match variable {
+            // A bad value only affects this branch.
+            "autorebase" => match parse_bool(value) {
+                Some(autorebase) => {
+                    config.autorebase = Some(autorebase);
+                    config.error = None;
+                }
+                None => {
+                    config.autorebase = None;
+                    config.error =
+                        Some(format!("Invalid boolean value '{}' for {}", value_str, key));
+                }
+            },
+            "autorebaseonto" => config.onto = Some(value_str.to_owned()),
+            "description" => config.description = Some(value_str.trim().to_owned()),
+            _ => {}
+        }
+    }
+    Ok(configs)
+}
+
+/// Parse a git config boolean, the same way git does. `None` (a key with no
+/// `=`) means true, but an empty value means false. Integers (optionally
+/// with a `k`, `m` or `g` suffix) are true if they aren't zero.
+fn parse_bool(value: Option<&str>) -> Option<bool> {
+    let Some(value) = value else {
+        return Some(true);
+    };
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "" | "false" | "no" | "off" => Some(false),
+        value => {
+            let number = value
+                .strip_suffix(['k', 'm', 'g'])
+                .unwrap_or(value)
+                .parse::<i64>()
+                .ok()?;
+            Some(number != 0)
+        }
+    }
+}
@@ -11,11 +11,17 @@ use std::path::Path;
 //
 // It's not perfect since if they are just working on that branch without rebasing
 // it we will keep retrying the rebase, but it'll do.
+//
+// We also record when we last rebased each branch, for `--min-interval-hours`.
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Conflicts {
     /// Map from branch to the commit it pointed to when it got stuck due to conflicts.
     pub branches: HashMap<String, String>,
+    /// Map from branch to when we last moved it, in seconds since the Unix
+    /// epoch.
+    #[serde(default)]
+    pub rebased: HashMap<String, i64>,
 }
 
 impl Conflicts {
@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::glob::Glob;
+
+/// Decides which branches to rebase by name. A branch is selected if it
+/// matches any of the include globs or regexes (or there aren't any), and
+/// doesn't match any of the exclude globs.
+#[derive(Debug, Default)]
+pub struct BranchFilter {
+    include: Vec<Glob>,
+    include_regex: Vec<Regex>,
+    exclude: Vec<Glob>,
+}
+
+impl BranchFilter {
+    /// Build a filter. `include` patterns starting with `!` are treated as
+    /// exclusions, like in `.gitignore`. Returns an error if any of the regexes
+    /// are invalid.
+    pub fn new(include: &[String], exclude: &[String], include_regex: &[String]) -> Result<Self> {
+        let mut filter = Self::default();
+
+        for pattern in include {
+            match pattern.strip_prefix('!') {
+                Some(negated) => filter.exclude.push(Glob::new(negated)),
+                None => filter.include.push(Glob::new(pattern)),
+            }
+        }
+        filter
+            .exclude
+            .extend(exclude.iter().map(|pattern| Glob::new(pattern)));
+
+        for pattern in include_regex {
+            filter.include_regex.push(
+                Regex::new(pattern)
+                    .with_context(|| format!("Invalid branch regex '{}'", pattern))?,
+            );
+        }
+
+        Ok(filter)
+    }
+
+    /// Should `branch` be rebased?
+    pub fn matches(&self, branch: &str) -> bool {
+        let included = (self.include.is_empty() && self.include_regex.is_empty())
+            || self.include.iter().any(|glob| glob.matches(branch))
+            || self
+                .include_regex
+                .iter()
+                .any(|regex| regex.is_match(branch));
+
+        included && !self.exclude.iter().any(|glob| glob.matches(branch))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str], include_regex: &[&str]) -> BranchFilter {
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        BranchFilter::new(
+            &strings(include),
+            &strings(exclude),
+            &strings(include_regex),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn filter_test() {
+        let all = filter(&[], &[], &[]);
+        assert!(all.matches("anything"));
+        assert!(all.matches("a/b"));
+
+        let except = filter(&[], &["wip/*", "archive/*"], &[]);
+        assert!(except.matches("feature"));
+        assert!(except.matches("wip"));
+        assert!(!except.matches("wip/a"));
+        assert!(!except.matches("archive/a"));
+        assert!(except.matches("archive/a/b"));
+
+        let negated = filter(&["!wip/**"], &[], &[]);
+        assert!(negated.matches("feature"));
+        assert!(!negated.matches("wip/a/b"));
+
+        let mixed = filter(&["feature/*", "!feature/old"], &[], &["^fix-[0-9]+$"]);
+        assert!(mixed.matches("feature/new"));
+        assert!(!mixed.matches("feature/old"));
+        assert!(mixed.matches("fix-123"));
+        assert!(!mixed.matches("fix-abc"));
+        assert!(!mixed.matches("other"));
+
+        assert!(BranchFilter::new(&[], &[], &["(".to_owned()]).is_err());
+    }
+}
@@ -1,59 +1,182 @@
-use itertools::{Itertools, Position};
+// Gitignore-style glob matching for branch names. Matching is done on path
+// segments, so `*` and `?` never match `/`. The supported syntax is:
+//
+// * `*` matches zero or more characters within a segment.
+// * `?` matches a single character within a segment.
+// * `[abc]`, `[a-z]` match one character from a set; `[!a-z]` or `[^a-z]`
+//   match one character not in the set.
+// * `**` as a whole segment matches zero or more segments, so `a/**/b` matches
+//   `a/b` and `a/x/y/b`, `**/b` matches `b` anywhere, and `a/**` matches
+//   everything inside `a`. Elsewhere `**` is the same as `*`.
+// * `\` escapes the next character.
 
-/// Simple *-based glob matching.
-pub fn glob_match(pattern: &str, string: &str) -> bool {
-    // Special handling is needed for this case.
-    if pattern.is_empty() {
-        return string.is_empty();
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A literal character.
+    Literal(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    Star,
+    /// `**` at the end of the pattern; matches anything, including `/`.
+    AnyPath,
+    /// `**/`; matches zero or more whole segments.
+    AnyDirs,
+    /// `[...]`
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
 
-    // Split by *.
-    let parts = pattern.split('*');
+/// A compiled glob pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
 
-    // Current index in string.
-    let mut index = 0;
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
 
-    for (position, part) in parts.with_position() {
-        match position {
-            Position::First => {
-                // If it's the very first one then it must match at the beginning,
-                // otherwise there was a * before so we take the first match.
-                if !string.starts_with(part) {
-                    return false;
-                }
-                index += part.len();
-            }
-            Position::Middle => {
-                // In the middle there must be a * before, so we find the
-                // first match.
-                match string[index..].find(part) {
-                    Some(offset) => {
-                        index += offset + part.len();
+        while i < chars.len() {
+            match chars[i] {
+                '*' => {
+                    let start = i;
+                    while i < chars.len() && chars[i] == '*' {
+                        i += 1;
                     }
-                    None => {
-                        return false;
+                    let at_segment_start = start == 0 || chars[start - 1] == '/';
+                    if i - start >= 2 && at_segment_start {
+                        if i == chars.len() {
+                            tokens.push(Token::AnyPath);
+                            continue;
+                        }
+                        if chars[i] == '/' {
+                            tokens.push(Token::AnyDirs);
+                            i += 1;
+                            continue;
+                        }
                     }
+                    tokens.push(Token::Star);
+                    continue;
                 }
-            }
-            Position::Last => {
-                // If this is the last part then the remaining string must end
-                // with this.
-                if !string[index..].ends_with(part) {
-                    return false;
+                '?' => tokens.push(Token::AnyChar),
+                '[' => {
+                    if let Some((class, end)) = parse_class(&chars, i) {
+                        tokens.push(class);
+                        i = end;
+                        continue;
+                    }
+                    // Unterminated; treat it literally.
+                    tokens.push(Token::Literal('['));
+                }
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    tokens.push(Token::Literal(chars[i]));
                 }
+                c => tokens.push(Token::Literal(c)),
+            }
+            i += 1;
+        }
+
+        Self { tokens }
+    }
+
+    pub fn matches(&self, string: &str) -> bool {
+        let chars: Vec<char> = string.chars().collect();
+        match_tokens(&self.tokens, &chars)
+    }
+}
+
+/// Parse a character class starting at `chars[start]` (which is `[`). Returns
+/// the token and the index just after the closing `]`, or `None` if there
+/// isn't one.
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+
+    loop {
+        let c = *chars.get(i)?;
+        // A `]` straight after the `[` is literal.
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+
+        let c = if c == '\\' {
+            i += 1;
+            *chars.get(i)?
+        } else {
+            c
+        };
+
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&e| e != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+}
+
+fn match_tokens(tokens: &[Token], string: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return string.is_empty();
+    };
+
+    match token {
+        Token::Literal(c) => string.first() == Some(c) && match_tokens(rest, &string[1..]),
+        Token::AnyChar => {
+            matches!(string.first(), Some(&c) if c != '/') && match_tokens(rest, &string[1..])
+        }
+        Token::Class { negated, ranges } => match string.first() {
+            Some(&c) if c != '/' => {
+                let in_class = ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+                in_class != *negated && match_tokens(rest, &string[1..])
             }
-            Position::Only => {
-                return pattern == part;
+            _ => false,
+        },
+        Token::Star => {
+            // Try every length up to the next `/`.
+            for i in 0..=string.len() {
+                if match_tokens(rest, &string[i..]) {
+                    return true;
+                }
+                if string.get(i) == Some(&'/') {
+                    break;
+                }
             }
+            false
+        }
+        Token::AnyPath => true,
+        Token::AnyDirs => {
+            // Zero segments, or skip to just after any `/`.
+            match_tokens(rest, string)
+                || (0..string.len())
+                    .filter(|&i| string[i] == '/')
+                    .any(|i| match_tokens(rest, &string[i + 1..]))
         }
     }
-    true
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn glob_match(pattern: &str, string: &str) -> bool {
+        Glob::new(pattern).matches(string)
+    }
+
     #[test]
     fn glob_test() {
         assert!(glob_match("", ""));
@@ -84,4 +207,55 @@ mod test {
         assert!(glob_match("a*bcd*bcd*ef", "aabcdbcdbcdabcdefefefggef"));
         assert!(!glob_match("a*bcd*bcd*ef", "abcdef"));
     }
+
+    #[test]
+    fn glob_segments() {
+        assert!(glob_match("feature/*", "feature/a"));
+        assert!(!glob_match("feature/*", "feature/a/b"));
+        assert!(!glob_match("*", "a/b"));
+        assert!(!glob_match("a?b", "a/b"));
+
+        assert!(glob_match("feature/**", "feature/a"));
+        assert!(glob_match("feature/**", "feature/a/b"));
+        assert!(!glob_match("feature/**", "feature"));
+        assert!(!glob_match("feature/**", "features/a"));
+
+        assert!(glob_match("**/fix", "fix"));
+        assert!(glob_match("**/fix", "a/fix"));
+        assert!(glob_match("**/fix", "a/b/fix"));
+        assert!(!glob_match("**/fix", "afix"));
+
+        assert!(glob_match("a/**/b", "a/b"));
+        assert!(glob_match("a/**/b", "a/x/b"));
+        assert!(glob_match("a/**/b", "a/x/y/b"));
+        assert!(!glob_match("a/**/b", "a/xb"));
+
+        // Not a whole segment so it's the same as `*`.
+        assert!(glob_match("a**", "ab"));
+        assert!(!glob_match("a**", "a/b"));
+    }
+
+    #[test]
+    fn glob_chars() {
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+
+        assert!(glob_match("v[0-9]", "v5"));
+        assert!(!glob_match("v[0-9]", "vx"));
+        assert!(glob_match("v[!0-9]", "vx"));
+        assert!(glob_match("v[^0-9]", "vx"));
+        assert!(!glob_match("v[!0-9]", "v5"));
+        assert!(glob_match("[abc]", "b"));
+        assert!(!glob_match("[abc]", "d"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[a-]", "-"));
+        assert!(!glob_match("[a]", "/"));
+
+        // Unterminated classes are literal.
+        assert!(glob_match("a[b", "a[b"));
+
+        assert!(glob_match("a\\*", "a*"));
+        assert!(!glob_match("a\\*", "ab"));
+        assert!(glob_match("\\[a]", "[a]"));
+    }
 }
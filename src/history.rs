@@ -0,0 +1,310 @@
+use anyhow::Result;
+use colored::*;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fmt,
+    path::Path,
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::*;
+
+// Our rebases are recorded in the reflog with messages starting
+// `autorebase: `, so that they can be told apart from the user's own and
+// listed by `autorebase log`. `git rebase` uses `GIT_REFLOG_ACTION` as the
+// start of its messages, e.g.
+//
+//   autorebase: full rebase onto <commit> (run <id>) (finish): refs/heads/wip onto <commit>
+//
+// When we move a branch ourselves we use the same message without the suffix.
+// The run ID groups the entries by run, since their times can differ.
+
+const REFLOG_PREFIX: &str = "autorebase: ";
+
+/// What a rebase was trying to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseKind {
+    /// Rebase all the way onto the target.
+    Full,
+    /// Rebase part of the way onto the target, because of conflicts.
+    Partial,
+    /// Squash fixup commits without changing the base.
+    Squash,
+}
+
+impl RebaseKind {
+    fn description(self) -> &'static str {
+        match self {
+            Self::Full => "full rebase",
+            Self::Partial => "partial rebase",
+            Self::Squash => "squash",
+        }
+    }
+}
+
+impl fmt::Display for RebaseKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// A new ID for an autorebase run, which is unique enough to tell runs in
+/// the same repo apart.
+pub(crate) fn new_run_id() -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{:x}-{:x}", time.as_nanos(), process::id())
+}
+
+/// The reflog message for a rebase of `kind` onto the commit `onto` in the
+/// run `run_id`.
+pub(crate) fn reflog_action(run_id: &str, kind: RebaseKind, onto: &str) -> String {
+    format!("{}{} onto {} (run {})", REFLOG_PREFIX, kind, onto, run_id)
+}
+
+/// A parsed reflog message written by us.
+#[derive(Debug, PartialEq, Eq)]
+struct ReflogMessage<'a> {
+    kind: RebaseKind,
+    onto: &'a str,
+    run_id: &'a str,
+}
+
+/// Parse a reflog message, if it was written by us.
+fn parse_reflog_message(message: &str) -> Option<ReflogMessage<'_>> {
+    let (kind, rest) = message.strip_prefix(REFLOG_PREFIX)?.split_once(" onto ")?;
+    let kind = [RebaseKind::Full, RebaseKind::Partial, RebaseKind::Squash]
+        .into_iter()
+        .find(|k| k.description() == kind)?;
+    let (onto, rest) = rest.split_once(' ')?;
+    let (run_id, _) = rest.strip_prefix("(run ")?.split_once(')')?;
+    Some(ReflogMessage { kind, onto, run_id })
+}
+
+/// A branch being moved by autorebase, according to its reflog.
+#[derive(Debug)]
+pub struct LoggedRebase {
+    pub branch: String,
+    pub kind: RebaseKind,
+    /// The commit it was rebased onto.
+    pub onto: String,
+    /// The commit the branch was at before, if the reflog goes back that far.
+    pub old_commit: Option<String>,
+    /// The commit the branch was moved to.
+    pub new_commit: String,
+}
+
+/// All the branches moved by one autorebase run.
+#[derive(Debug)]
+pub struct LoggedRun {
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    pub rebases: Vec<LoggedRebase>,
+}
+
+/// List previous autorebase runs in the repo containing `path`, newest first,
+/// by reading the reflogs of all branches. Branches that weren't changed
+/// aren't recorded, so runs where nothing was rebased don't appear.
+pub fn rebase_log(path: &Path) -> Result<Vec<LoggedRun>> {
+    let mut rebases = Vec::new();
+    for branch in get_branches(path)? {
+        rebases.extend(branch_log(path, &branch.branch)?);
+    }
+
+    rebases.sort_by_key(|entry| Reverse(entry.time));
+    let mut runs: Vec<LoggedRun> = Vec::new();
+    let mut run_indices: HashMap<String, usize> = HashMap::new();
+    for LoggedEntry {
+        time,
+        run_id,
+        rebase,
+    } in rebases
+    {
+        // The run's time is that of its newest entry.
+        let index = *run_indices.entry(run_id).or_insert_with(|| {
+            runs.push(LoggedRun {
+                time,
+                rebases: Vec::new(),
+            });
+            runs.len() - 1
+        });
+        runs[index].rebases.push(rebase);
+    }
+    for run in &mut runs {
+        run.rebases.sort_by(|a, b| a.branch.cmp(&b.branch));
+    }
+    Ok(runs)
+}
+
+/// One of our entries in a branch's reflog.
+struct LoggedEntry {
+    /// Seconds since the Unix epoch.
+    time: i64,
+    run_id: String,
+    rebase: LoggedRebase,
+}
+
+/// Our entries in the reflog of `branch`.
+fn branch_log(working_dir: &Path, branch: &str) -> Result<Vec<LoggedEntry>> {
+    let output = git(
+        &[
+            "reflog",
+            "show",
+            "--date=unix",
+            "--format=%gd%x00%H%x00%gs",
+            &format!("refs/heads/{}", branch),
+            "--",
+        ],
+        working_dir,
+    )?
+    .stdout;
+    let output = String::from_utf8(output)?;
+
+    // Each line is `<branch>@{<time>}\0<new commit>\0<message>`, newest first.
+    let entries: Vec<Vec<&str>> = output
+        .lines()
+        .map(|line| line.split('\0').collect())
+        .collect();
+
+    let mut rebases = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let [selector, new_commit, message] = entry.as_slice() else {
+            continue;
+        };
+        let Some(ReflogMessage { kind, onto, run_id }) = parse_reflog_message(message) else {
+            continue;
+        };
+        let Some(time) = selector
+            .rsplit_once("@{")
+            .and_then(|(_, time)| time.strip_suffix('}'))
+            .and_then(|time| time.parse().ok())
+        else {
+            continue;
+        };
+        rebases.push(LoggedEntry {
+            time,
+            run_id: run_id.to_owned(),
+            rebase: LoggedRebase {
+                branch: branch.to_owned(),
+                kind,
+                onto: onto.to_owned(),
+                old_commit: entries
+                    .get(index + 1)
+                    .and_then(|e| e.get(1))
+                    .map(|&c| c.to_owned()),
+                new_commit: new_commit.to_string(),
+            },
+        });
+    }
+    Ok(rebases)
+}
+
+/// Print the runs from `rebase_log()`.
+pub fn print_rebase_log(runs: &[LoggedRun]) {
+    if runs.is_empty() {
+        println!("No branches have been rebased by autorebase");
+        return;
+    }
+
+    for run in runs {
+        println!("• {}", format_time(run.time).bold());
+
+        let width = run
+            .rebases
+            .iter()
+            .map(|r| r.branch.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        for rebase in &run.rebases {
+            let old_commit = rebase.old_commit.as_deref().map_or("?", short_hash);
+            println!(
+                "    {:width$}  {} onto {}  {}",
+                rebase.branch.bold(),
+                rebase.kind,
+                short_hash(&rebase.onto).blue(),
+                format!("({} -> {})", old_commit, short_hash(&rebase.new_commit)).dimmed(),
+                width = width
+            );
+        }
+    }
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(10)]
+}
+
+/// Format seconds since the Unix epoch as a UTC date and time.
+fn format_time(time: i64) -> String {
+    let days = time.div_euclid(86400);
+    let seconds = time.rem_euclid(86400);
+
+    // Convert days since 1970-01-01 to a date. See
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_reflog_messages() {
+        let onto = "57de7a77cb3b82d9e66dbf3ed30c997d81e1c79b";
+        let run_id = "18a2b3c4d5e6f708-1f40";
+        assert_eq!(
+            parse_reflog_message(&reflog_action(run_id, RebaseKind::Partial, onto)),
+            Some(ReflogMessage {
+                kind: RebaseKind::Partial,
+                onto,
+                run_id
+            })
+        );
+        assert_eq!(
+            parse_reflog_message(&format!(
+                "{} (finish): refs/heads/wip onto {}",
+                reflog_action(run_id, RebaseKind::Full, onto),
+                onto
+            )),
+            Some(ReflogMessage {
+                kind: RebaseKind::Full,
+                onto,
+                run_id
+            })
+        );
+        assert_eq!(
+            parse_reflog_message(&format!("rebase (finish): refs/heads/wip onto {}", onto)),
+            None
+        );
+        assert_eq!(parse_reflog_message("commit: autorebase: fix"), None);
+    }
+
+    #[test]
+    fn format_times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_time(1792363190), "2026-10-18 22:39:50 UTC");
+        assert_eq!(format_time(-1), "1969-12-31 23:59:59 UTC");
+    }
+}
@@ -1,22 +1,54 @@
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use colored::*;
 use git_commands::*;
 use std::{
+    collections::HashSet,
     env,
     fs::read_to_string,
     path::{Component, Path, PathBuf},
-    process::Command,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+#[macro_use]
+mod output;
 mod config;
 use config::*;
 mod conflicts;
 use conflicts::*;
+mod filter;
+pub use filter::BranchFilter;
 mod glob;
-use glob::*;
+mod history;
+pub use history::*;
+mod lock;
+pub use lock::RepoLock;
+mod outcome;
+pub use outcome::*;
+mod progress;
+use progress::*;
+mod quality;
+pub use quality::QualityCheck;
+use quality::*;
+mod resolve;
+pub use resolve::*;
+mod scratch;
+use scratch::*;
+mod status;
+pub use status::*;
+mod submodules;
+use submodules::*;
+mod target;
+pub use target::TargetCommit;
+use target::*;
+mod signing;
+pub use signing::SigningPolicy;
+use signing::*;
+mod trace;
+pub use trace::*;
 mod trim;
 use trim::*;
+mod workspace;
+pub use workspace::*;
 
 // Set GIT_COMMITTER_DATE to now to prevent getting inconsistent hashes when
 // rebasing the same commit multiple times.
@@ -36,68 +68,214 @@ fn set_committer_date_to_now() {
     );
 }
 
-/// Autorebase all branches in the repo containing `path` onto the `onto_branch`
-/// (typically "master"). If `slow_conflict_detection` is true it will try every
-/// commit when there is a conflict until one works. Reliably but slow. If it is
-/// false it will try to detect the first commit that causes a conflict and
-/// rebase to just before that. Way faster, but may not always work.
+/// Options controlling `autorebase()`.
+#[derive(Debug, Default)]
+pub struct AutorebaseOptions {
+    /// The branch to rebase onto. If `None` it is read from `init.defaultBranch`
+    /// and defaults to "master".
+    pub onto_branch: Option<String>,
+    /// Which commit to rebase onto. By default it is the tip of the target
+    /// branch (after pulling it).
+    pub onto_commit: TargetCommit,
+    /// Only rebase onto commits on the target branch that pass this check,
+    /// e.g. because they passed CI. Each branch is rebased onto the newest
+    /// one that does.
+    pub quality_check: Option<QualityCheck>,
+    /// If true, when there is a conflict try every commit on the target
+    /// branch until one works. Reliable but slow. If false, try to detect
+    /// the first commit that causes a conflict and rebase to just before
+    /// that. Way faster, but may not always work.
+    pub slow_conflict_detection: bool,
+    /// Also rebase branches that have an upstream.
+    pub include_non_local: bool,
+    /// Only rebase branches matching this filter.
+    pub branch_filter: BranchFilter,
+    /// Only rebase branches whose tip commit was authored by the current
+    /// `user.email`.
+    pub only_mine: bool,
+    /// Only rebase branches whose tip commit was authored within this time.
+    pub max_age: Option<Duration>,
+    /// Don't rebase branches that we rebased less than this long ago.
+    pub min_interval: Option<Duration>,
+    /// Rebase branches despite `min_interval` if the target has more than
+    /// this many commits they don't have.
+    pub min_interval_commits: Option<usize>,
+    /// If another autorebase is running on this repo, wait for it to finish
+    /// instead of returning an error.
+    pub wait_for_lock: bool,
+    /// Whether to sign rebased commits.
+    pub signing_policy: SigningPolicy,
+    /// Squash `fixup!` and `squash!` commits into the commits they fix, like
+    /// `git rebase --interactive --autosquash`.
+    pub autosquash: bool,
+    /// If a branch and the target both changed a submodule's commit, take the
+    /// newer one if one is a descendant of the other.
+    pub resolve_submodules: bool,
+    /// Also autorebase the submodules that are checked out, after this repo.
+    pub recurse_submodules: bool,
+    /// Only check out these directories (and the files at the top level) in
+    /// our scratch worktree, using cone mode sparse-checkout. If empty it
+    /// gets the sparse-checkout settings of the worktree we were run from.
+    pub sparse_checkout: Vec<String>,
+}
+
+/// Information needed to rebase each branch.
+struct RebaseContext<'a> {
+    /// Path to the main `.git` directory.
+    git_common_dir: &'a Path,
+    /// Where to save the list of branches that are stuck due to conflicts.
+    conflicts_path: &'a Path,
+    /// The branch we are rebasing onto.
+    onto_branch: &'a str,
+    /// The commit we are rebasing onto. See `AutorebaseOptions::onto_commit`.
+    target_commit: &'a str,
+    /// Our private scratch worktree.
+    worktree_path: &'a Path,
+    options: &'a AutorebaseOptions,
+    /// Whether commits can be signed without user interaction.
+    signing_available: bool,
+    /// Which kind of `git replay` is available.
+    replay: Replay,
+    /// Identifies this run in the reflog (see `new_run_id()`).
+    run_id: &'a str,
+    /// Only rebase onto target commits that pass this.
+    quality_checker: Option<&'a QualityChecker>,
+}
+
+/// Get the target branch name in this priority order:
 ///
-pub fn autorebase(
-    path: &Path,
-    onto_branch: Option<&str>,
-    slow_conflict_detection: bool,
-    include_non_local: bool,
-    match_branches: Option<&str>,
-) -> Result<()> {
-    // Check the git version. `git switch` was introduced in 2.23.
-    if git_version()?.as_slice() < &[2, 23] {
-        bail!("Your Git installation is too old - version 2.23 or later is required");
-    }
-
-    // Get the target branch name in this priority order:
-    //
-    // 1. Set explicitly via `--onto`
-    // 2. The `init.defaultBranch` git config setting.
-    // 3. "master"
-    let onto_branch = match onto_branch {
-        Some(b) => b.to_owned(),
-        None => default_branch_name(path)?,
-    };
+/// 1. Set explicitly via `--onto`
+/// 2. The `init.defaultBranch` git config setting.
+/// 3. "master"
+fn target_branch_name(path: &Path, options: &AutorebaseOptions) -> Result<String> {
+    match &options.onto_branch {
+        Some(b) => Ok(b.clone()),
+        None => default_branch_name(path),
+    }
+}
 
-    // The first thing we do is set the committer date to now. If we don't do this
-    // then when we have two branch labels on the same commit, when they get
-    // rebased they will be given different committer dates which will mean they
-    // get different hashes and end up as separate commits.
-    set_committer_date_to_now();
+/// A repo that has been locked and prepared for rebasing.
+struct Session {
+    /// The branch we are rebasing onto.
+    onto_branch: String,
+    /// The root of the worktree containing the path we were run from.
+    worktree_root_path: PathBuf,
+    /// Path to the main `.git` directory.
+    git_common_dir: PathBuf,
+    /// Where `conflicts` is saved.
+    conflicts_path: PathBuf,
+    /// Branches that are stuck due to conflicts.
+    conflicts: Conflicts,
+    /// Our private scratch worktree.
+    autorebase_worktree_path: PathBuf,
+    /// Which kind of `git replay` is available.
+    replay: Replay,
+    /// Held until we are done.
+    _lock: RepoLock,
+}
 
-    // The path to the worktree root. This will normally be the root of the
-    // main repo, but if you are in another worktree it will be the root there
-    // instead.
-    let worktree_root_path = get_worktree_path(path)?;
+impl Session {
+    /// Find the repo containing `path`, lock it and prepare it for rebasing.
+    fn open(path: &Path, options: &AutorebaseOptions) -> Result<Self> {
+        // Check the git version. `git switch` was introduced in 2.23 and
+        // `git rebase --reapply-cherry-picks` in 2.27.
+        let git_version = git_version()?;
+        if git_version.as_slice() < &[2, 27] {
+            bail!("Your Git installation is too old - version 2.27 or later is required");
+        }
+        // This is when `git sparse-checkout` started using per-worktree
+        // settings. Before that it would change the main worktree too.
+        if !options.sparse_checkout.is_empty() && git_version.as_slice() < &[2, 36] {
+            bail!("Git 2.36 or later is required for sparse checkouts");
+        }
 
-    // Get the path to the main `.git` directory.
-    let git_common_dir = get_git_common_dir(&worktree_root_path)?;
+        let onto_branch = target_branch_name(path, options)?;
 
-    let conflicts_path = git_common_dir.join("autorebase/conflicts.toml");
+        // The first thing we do is set the committer date to now. If we don't do this
+        // then when we have two branch labels on the same commit, when they get
+        // rebased they will be given different committer dates which will mean they
+        // get different hashes and end up as separate commits.
+        set_committer_date_to_now();
 
-    let mut conflicts = if conflicts_path.is_file() {
-        Conflicts::read_from_file(&conflicts_path)?
-    } else {
-        Default::default()
-    };
+        // The path to the worktree root. This will normally be the root of the
+        // main repo, but if you are in another worktree it will be the root there
+        // instead.
+        let worktree_root_path = get_worktree_path(path)?;
+
+        // Get the path to the main `.git` directory.
+        let git_common_dir = get_git_common_dir(&worktree_root_path)?;
 
-    let autorebase_worktree_path = git_common_dir.join("autorebase/autorebase_worktree");
+        // Make sure nobody else is using the scratch worktree or conflicts file
+        // while we are. This is released when the session is dropped.
+        let lock = RepoLock::acquire(&git_common_dir, options.wait_for_lock)?;
 
-    if !autorebase_worktree_path.is_dir() {
-        eprint!("{}", "• Creating worktree...".yellow());
-        // The `git worktree add` command can be run from any worktree.
-        create_scratch_worktree(&worktree_root_path, &autorebase_worktree_path)?;
-        eprintln!("\r{}", "• Creating worktree...".green());
+        let conflicts_path = git_common_dir.join("autorebase/conflicts.toml");
+
+        let conflicts = if conflicts_path.is_file() {
+            Conflicts::read_from_file(&conflicts_path)?
+        } else {
+            Default::default()
+        };
+
+        let autorebase_worktree_path = git_common_dir.join(SCRATCH_WORKTREE_DIR);
+
+        // Give our worktree the same sparse-checkout settings as the one we
+        // were run from, unless we were told which directories to use.
+        let inherit_sparse_from =
+            (git_version.as_slice() >= &[2, 36]).then_some(worktree_root_path.as_path());
+
+        if !autorebase_worktree_path.is_dir() {
+            report!("{}", "• Creating worktree...".yellow());
+            // The `git worktree add` command can be run from any worktree.
+            create_scratch_worktree(
+                &worktree_root_path,
+                &autorebase_worktree_path,
+                &options.sparse_checkout,
+                inherit_sparse_from,
+            )?;
+            reportln!("\r{}", "• Creating worktree...".green());
+        } else {
+            prepare_scratch_worktree(
+                &autorebase_worktree_path,
+                &options.sparse_checkout,
+                inherit_sparse_from,
+            )?;
+        }
+
+        Ok(Self {
+            onto_branch,
+            worktree_root_path,
+            git_common_dir,
+            conflicts_path,
+            conflicts,
+            autorebase_worktree_path,
+            replay: Replay::for_version(&git_version),
+            _lock: lock,
+        })
     }
+}
+
+/// Autorebase all branches in the repo containing `path` onto the target branch
+/// (typically "master"). See `AutorebaseOptions` for the available settings.
+///
+/// Errors rebasing individual branches are recorded in the returned
+/// `RunOutcome` and don't stop the other branches from being rebased.
+///
+pub fn autorebase(path: &Path, options: &AutorebaseOptions) -> Result<RunOutcome> {
+    let Session {
+        onto_branch,
+        worktree_root_path,
+        git_common_dir,
+        conflicts_path,
+        mut conflicts,
+        autorebase_worktree_path,
+        replay,
+        _lock,
+    } = Session::open(path, options)?;
 
     // For each branch, find the common ancestor with `master`. There must only be one.
 
-    eprint!("{}", "• Getting branches...".yellow());
+    report!("{}", "• Getting branches...".yellow());
     // We can get branches from any worktree.
     let all_branches = get_branches(&worktree_root_path)?;
     let onto_branch_info = all_branches
@@ -105,153 +283,569 @@ pub fn autorebase(
         .find(|b| b.branch == onto_branch)
         .ok_or_else(|| anyhow!("Couldn't find target branch '{}'. You can set the default target \
                                     branch via 'git config init.defaultBranch' or use the --onto flag.", onto_branch))?;
-    eprintln!("\r{}", "• Getting branches...".green());
+    reportln!("\r{}", "• Getting branches...".green());
+
+    // Forget when we rebased branches that have since been deleted.
+    let rebased_count = conflicts.rebased.len();
+    conflicts
+        .rebased
+        .retain(|branch, _| all_branches.iter().any(|b| b.branch == *branch));
+    if conflicts.rebased.len() != rebased_count {
+        conflicts.write_to_file(&conflicts_path)?;
+    }
+
+    let current_user_email = current_user_email_if_needed(&worktree_root_path, options)?;
 
     // Print a summary of the branches, and simultaneously filter them.
     let mut rebase_branches: Vec<&BranchInfo> = Vec::with_capacity(all_branches.len());
 
+    let mut outcome = RunOutcome {
+        target_branch: onto_branch.clone(),
+        target_pull: TargetPull::NoUpstream,
+        branches: Vec::new(),
+        submodules: Vec::new(),
+    };
+
     for branch in all_branches.iter() {
         if branch.branch == onto_branch {
-            eprintln!("    - {} (target branch)", branch.branch.blue().bold());
+            reportln!("    - {} (target branch)", branch.branch.blue().bold());
             continue;
         }
-        if match match_branches {
-            Some(glob) => !glob_match(glob, &branch.branch),
-            None => false,
-        } {
-            eprintln!(
-                "    - {} (skipping because it does not match branch filter)",
-                branch.branch.bold()
-            );
-            continue;
-        }
-        if !include_non_local && branch.upstream.is_some() {
-            eprintln!(
-                "    - {} (skipping because it has an upstream)",
-                branch.branch.bold()
-            );
-            continue;
-        }
-        if matches!(&branch.worktree, Some(worktree) if !worktree.clean) {
-            eprintln!(
-                "    - {} (skipping because it is checked out and not clean)",
-                branch.branch.bold()
+        if let Some(reason) = skip_reason(branch, options, current_user_email.as_deref()) {
+            reportln!(
+                "    - {} (skipping because it {})",
+                branch.branch.bold(),
+                reason
             );
+            outcome.branches.push(BranchResult {
+                branch: branch.branch.clone(),
+                outcome: BranchOutcome::Skipped(reason),
+            });
             continue;
         }
 
-        eprintln!("    - {}", branch.branch.green().bold());
+        reportln!("    - {}", branch.branch.green().bold());
         rebase_branches.push(branch);
     }
 
+    let rebase_branches = order_by_target(&rebase_branches);
+
     // Pull master.
-    pull_master(onto_branch_info, &autorebase_worktree_path)?;
+    outcome.target_pull = pull_master(onto_branch_info, &autorebase_worktree_path)?;
+
+    let target_commit = resolve_target_commit(
+        &autorebase_worktree_path,
+        &onto_branch,
+        &options.onto_commit,
+    )?;
+    if options.onto_commit != TargetCommit::Tip {
+        reportln!(
+            "• Rebasing onto {} ({})",
+            target_commit.bold(),
+            options.onto_commit
+        );
+    }
+
+    // Check that signing works before we start, otherwise we may get prompted
+    // for a passphrase for every commit, or fail halfway through a rebase.
+    let signing_available = if options.signing_policy.may_sign(&worktree_root_path)? {
+        report!("{}", "• Checking commit signing...".yellow());
+        let available = signing_available(&autorebase_worktree_path, &onto_branch);
+        if available {
+            reportln!("\r{}", "• Checking commit signing...".green());
+        } else {
+            reportln!(
+                "\r{}",
+                "• Warning: Commit signing is not available; branches that need signing will be skipped"
+                    .yellow()
+            );
+        }
+        available
+    } else {
+        false
+    };
+
+    let quality_checker = options
+        .quality_check
+        .as_ref()
+        .map(|check| QualityChecker::new(check, &worktree_root_path))
+        .transpose()?;
+
+    let context = RebaseContext {
+        git_common_dir: &git_common_dir,
+        conflicts_path: &conflicts_path,
+        onto_branch: &onto_branch,
+        target_commit: &target_commit,
+        worktree_path: &autorebase_worktree_path,
+        options,
+        signing_available,
+        replay,
+        run_id: &new_run_id(),
+        quality_checker: quality_checker.as_ref(),
+    };
 
     for branch in rebase_branches.iter() {
-        rebase_branch(
-            branch,
-            &git_common_dir,
-            &mut conflicts,
-            &conflicts_path,
-            &onto_branch,
-            &autorebase_worktree_path,
-            slow_conflict_detection,
-        )?;
+        let old_commit = get_commit_hash(&worktree_root_path, &branch.branch).ok();
+        let branch_outcome = match rebase_branch(branch, &context, &mut conflicts) {
+            Ok(branch_outcome) => branch_outcome,
+            Err(e) => {
+                reportln!(
+                    "\n{} {}\n{:?}",
+                    "    - Error rebasing".red(),
+                    branch.branch.red().bold(),
+                    e
+                );
+                // If we can't get back to a known state then carrying on
+                // would probably just make a mess of the other branches.
+                // This has to happen even if we were cancelled.
+                uncancellable(|| recover_from_failed_rebase(branch, &context)).map_err(
+                    |recovery_error| {
+                        anyhow!(
+                            "Couldn't recover after error rebasing '{}': {:?}",
+                            branch.branch,
+                            recovery_error
+                        )
+                    },
+                )?;
+                if is_cancelled() {
+                    return Err(e);
+                }
+                BranchOutcome::Failed(e)
+            }
+        };
+        // Remember when branches were moved, for `min_interval`. A branch
+        // that got stuck on its first commit hasn't moved.
+        let moved = match branch_outcome {
+            BranchOutcome::Rebased => true,
+            BranchOutcome::Conflicts => {
+                get_commit_hash(&worktree_root_path, &branch.branch).ok() != old_commit
+            }
+            _ => false,
+        };
+        if moved {
+            conflicts
+                .rebased
+                .insert(branch.branch.clone(), unix_time_now());
+            conflicts.write_to_file(&conflicts_path)?;
+        }
+        outcome.branches.push(BranchResult {
+            branch: branch.branch.clone(),
+            outcome: branch_outcome,
+        });
     }
 
-    Ok(())
+    if options.recurse_submodules {
+        let submodules = checked_out_submodules(&worktree_root_path)?;
+        outcome.submodules = autorebase_repos(&submodules, options, 1).repos;
+    }
+
+    Ok(outcome)
 }
 
-/// Pull the master branch (the `onto` branch), if it has an upstream.
-fn pull_master(onto_branch_info: &BranchInfo, worktree_path: &Path) -> Result<(), anyhow::Error> {
-    if onto_branch_info.upstream.is_some() {
-        if let Some(onto_branch_worktree_info) = &onto_branch_info.worktree {
-            // It's checked out somewhere. Check if that worktree is clean,
-            // if so pull it there.
-            if onto_branch_worktree_info.clean {
-                eprint!(
-                    "{} {}{}",
-                    "• Pulling".yellow(),
-                    onto_branch_info.branch.yellow().bold(),
-                    "...".yellow(),
-                );
+/// Get `user.email` if we need it to filter branches.
+fn current_user_email_if_needed(
+    working_dir: &Path,
+    options: &AutorebaseOptions,
+) -> Result<Option<String>> {
+    if !options.only_mine {
+        return Ok(None);
+    }
+    match user_email(working_dir)? {
+        Some(email) => Ok(Some(email)),
+        None => bail!("user.email must be set to only rebase your own branches"),
+    }
+}
 
-                git(&["pull", "--ff-only"], &onto_branch_worktree_info.path)?;
+/// If `branch` should not be rebased given the `options` and its git config,
+/// return why.
+/// `user_email` is the current user's email, which is only needed if
+/// `options.only_mine` is set.
+fn skip_reason(
+    branch: &BranchInfo,
+    options: &AutorebaseOptions,
+    user_email: Option<&str>,
+) -> Option<SkipReason> {
+    if branch.config.autorebase == Some(false) {
+        return Some(SkipReason::DisabledInConfig);
+    }
+    if !options.branch_filter.matches(&branch.branch) {
+        return Some(SkipReason::DoesNotMatchFilter);
+    }
+    if options.only_mine && user_email != Some(branch.author_email.as_str()) {
+        return Some(SkipReason::NotMine);
+    }
+    if let Some(max_age) = options.max_age {
+        if unix_time_now().saturating_sub(branch.author_date) > max_age.as_secs() as i64 {
+            return Some(SkipReason::TooOld);
+        }
+    }
+    if !options.include_non_local
+        && branch.upstream.is_some()
+        && branch.config.autorebase != Some(true)
+    {
+        return Some(SkipReason::HasUpstream);
+    }
+    if matches!(&branch.worktree, Some(worktree) if !worktree.clean) {
+        return Some(SkipReason::CheckedOutNotClean);
+    }
+    None
+}
 
-                eprintln!(
-                    "\r{} {}{}",
-                    "• Pulling".green(),
-                    onto_branch_info.branch.green().bold(),
-                    "...".green(),
-                );
-            } else {
-                eprintln!(
-                    "• Not pulling target branch {} because it is checked out and has pending changes",
-                    onto_branch_info.branch.bold(),
+/// Order `branches` so that branches that others are rebased onto (with
+/// `branch.<name>.autorebaseOnto`) come before them, so those are rebased onto
+/// where they end up rather than where they started. Otherwise the order is
+/// unchanged.
+fn order_by_target<'a>(branches: &[&'a BranchInfo]) -> Vec<&'a BranchInfo> {
+    fn visit<'a>(
+        branch: &'a BranchInfo,
+        branches: &[&'a BranchInfo],
+        visited: &mut HashSet<&'a str>,
+        ordered: &mut Vec<&'a BranchInfo>,
+    ) {
+        // This also stops us going round in circles if branches are rebased
+        // onto each other.
+        if !visited.insert(&branch.branch) {
+            return;
+        }
+        if let Some(target) = branches
+            .iter()
+            .find(|b| Some(&b.branch) == branch.config.onto.as_ref())
+        {
+            visit(target, branches, visited, ordered);
+        }
+        ordered.push(branch);
+    }
+
+    let mut visited = HashSet::new();
+    let mut ordered = Vec::with_capacity(branches.len());
+    for branch in branches {
+        visit(branch, branches, &mut visited, &mut ordered);
+    }
+    ordered
+}
+
+/// Did we rebase `branch` too recently to do it again, given that it is
+/// `behind` the target by that many commits? See `AutorebaseOptions::min_interval`.
+fn rebased_recently(
+    branch: &str,
+    behind: usize,
+    conflicts: &Conflicts,
+    options: &AutorebaseOptions,
+) -> bool {
+    let (Some(min_interval), Some(&rebased)) =
+        (options.min_interval, conflicts.rebased.get(branch))
+    else {
+        return false;
+    };
+    unix_time_now().saturating_sub(rebased) < min_interval.as_secs() as i64
+        && options
+            .min_interval_commits
+            .is_none_or(|commits| behind <= commits)
+}
+
+fn unix_time_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// The branch and commit that `branch` should be rebased onto. That is the
+/// target branch and `target_commit`, unless `branch.<name>.autorebaseOnto`
+/// names another branch, in which case it is the tip of that branch.
+fn branch_target(
+    branch: &BranchInfo,
+    onto_branch: &str,
+    target_commit: &str,
+    working_dir: &Path,
+) -> Result<(String, String)> {
+    match &branch.config.onto {
+        Some(onto) if onto != onto_branch => {
+            if *onto == branch.branch {
+                bail!(
+                    "branch.{}.autorebaseOnto is the branch itself",
+                    branch.branch
                 );
             }
-        } else {
-            eprint!(
-                "{} {}{}",
-                "• Pulling".yellow(),
-                onto_branch_info.branch.yellow().bold(),
-                "...".yellow(),
+            let commit = get_commit_hash(working_dir, &format!("refs/heads/{}", onto))
+                .with_context(|| {
+                    format!(
+                        "Couldn't find branch '{}' from branch.{}.autorebaseOnto",
+                        onto, branch.branch
+                    )
+                })?;
+            Ok((onto.clone(), commit))
+        }
+        _ => Ok((onto_branch.to_owned(), target_commit.to_owned())),
+    }
+}
+
+/// After an error rebasing `branch`, abort any rebase that is still in progress
+/// (which restores the branch to where it was) and put our scratch worktree
+/// back into its normal detached state.
+fn recover_from_failed_rebase(branch: &BranchInfo, context: &RebaseContext) -> Result<()> {
+    let mut worktree_paths = vec![context.worktree_path];
+    if let Some(worktree) = &branch.worktree {
+        worktree_paths.push(&worktree.path);
+    }
+
+    for worktree_path in worktree_paths {
+        let worktree = get_worktree_name(worktree_path)?;
+        if is_rebasing(context.git_common_dir, worktree.as_deref()) {
+            git_in_worktree(&["rebase", "--abort"], worktree_path)?;
+        }
+    }
+
+    // This is our private worktree so it's fine to throw away any changes.
+    git_in_worktree(
+        &[
+            "switch",
+            "--detach",
+            "--discard-changes",
+            context.onto_branch,
+        ],
+        context.worktree_path,
+    )?;
+
+    // This fails if it doesn't exist, which is fine.
+    let _ = git(
+        &["branch", "--delete", "--force", TEMPORARY_BRANCH_NAME],
+        context.worktree_path,
+    );
+
+    Ok(())
+}
+
+/// Pull the master branch (the `onto` branch), if it has an upstream. If
+/// pulling fails (e.g. because we're offline) we carry on with the local
+/// branch.
+fn pull_master(onto_branch_info: &BranchInfo, worktree_path: &Path) -> Result<TargetPull> {
+    if onto_branch_info.upstream.is_none() {
+        reportln!(
+            "{} {} {}",
+            "• Warning: Not pulling target branch".yellow(),
+            onto_branch_info.branch.yellow().bold(),
+            "because it has no upstream".yellow(),
+        );
+        return Ok(TargetPull::NoUpstream);
+    }
+
+    let pull_result = if let Some(onto_branch_worktree_info) = &onto_branch_info.worktree {
+        // It's checked out somewhere. Check if that worktree is clean,
+        // if so pull it there.
+        if !onto_branch_worktree_info.clean {
+            reportln!(
+                "• Not pulling target branch {} because it is checked out and has pending changes",
+                onto_branch_info.branch.bold(),
             );
+            return Ok(TargetPull::NotClean);
+        }
 
-            git(&["switch", &onto_branch_info.branch], worktree_path)?;
-            git(&["pull", "--ff-only"], worktree_path)?;
-            git(&["switch", "--detach"], worktree_path)?;
+        report!(
+            "{} {}{}",
+            "• Pulling".yellow(),
+            onto_branch_info.branch.yellow().bold(),
+            "...".yellow(),
+        );
 
-            eprintln!(
+        pull(&onto_branch_info.branch, &onto_branch_worktree_info.path)
+    } else {
+        report!(
+            "{} {}{}",
+            "• Pulling".yellow(),
+            onto_branch_info.branch.yellow().bold(),
+            "...".yellow(),
+        );
+
+        git_in_worktree(&["switch", &onto_branch_info.branch], worktree_path)?;
+        let pull_result = pull(&onto_branch_info.branch, worktree_path);
+        // This has to happen even if we were cancelled.
+        uncancellable(|| git_in_worktree(&["switch", "--detach"], worktree_path))?;
+        pull_result
+    };
+
+    match pull_result {
+        Ok(()) => {
+            reportln!(
                 "\r{} {}{}",
                 "• Pulling".green(),
                 onto_branch_info.branch.green().bold(),
                 "...".green(),
             );
+            Ok(TargetPull::Pulled)
+        }
+        Err(e) if is_cancelled() => Err(e),
+        Err(e) => {
+            reportln!(
+                "\r{} {}{}\n{:?}",
+                "• Couldn't pull target branch".yellow(),
+                onto_branch_info.branch.yellow().bold(),
+                "; rebasing onto the local branch".yellow(),
+                e
+            );
+            Ok(TargetPull::Failed(e))
         }
-    } else {
-        eprintln!(
-            "{} {} {}",
-            "• Warning: Not pulling target branch".yellow(),
-            onto_branch_info.branch.yellow().bold(),
-            "because it has no upstream".yellow(),
-        );
     }
+}
+
+/// `git pull --ff-only` the branch checked out in `worktree_path`, showing progress.
+fn pull(branch: &str, worktree_path: &Path) -> Result<()> {
+    let mut progress = ProgressLine::new(format!(
+        "{} {}{} ",
+        "• Pulling".yellow(),
+        branch.yellow().bold(),
+        "...".yellow()
+    ));
+    git_with_progress(
+        &["pull", "--ff-only", "--progress"],
+        worktree_path,
+        worktree_env(worktree_path),
+        &mut |line| progress.update(line),
+    )?;
     Ok(())
 }
 
 fn rebase_branch(
     branch: &BranchInfo,
-    git_common_dir: &Path,
+    context: &RebaseContext,
     conflicts: &mut Conflicts,
-    conflicts_path: &Path,
-    onto_branch: &str,
-    worktree_path: &Path,
-    slow_conflict_detection: bool,
-) -> Result<(), anyhow::Error> {
-    eprintln!("• Rebasing {} ...", branch.branch.bold());
+) -> Result<BranchOutcome> {
+    let RebaseContext {
+        git_common_dir,
+        conflicts_path,
+        onto_branch,
+        target_commit,
+        worktree_path,
+        ..
+    } = *context;
+
+    reportln!("• Rebasing {} ...", branch.branch.bold());
+
+    if let Some(error) = &branch.config.error {
+        bail!("{}", error);
+    }
+
+    let (onto_branch, target_commit) =
+        branch_target(branch, onto_branch, target_commit, worktree_path)?;
+    let own_target = onto_branch != context.onto_branch;
+    if own_target {
+        reportln!(
+            "    - Rebasing onto {} (from branch.{}.autorebaseOnto)",
+            onto_branch.bold(),
+            branch.branch
+        );
+    }
+    let (onto_branch, target_commit) = (onto_branch.as_str(), target_commit.as_str());
 
     let branch_commit = get_commit_hash(worktree_path, &branch.branch)?;
 
     if conflicts.branches.get(&branch.branch).map(|s| s.as_str()) == Some(&branch_commit) {
-        eprintln!(
+        reportln!(
             "{}",
             "    - Skipping rebase because it had conflicts last time we tried; rebase manually"
                 .yellow()
         );
-        return Ok(());
+        return Ok(BranchOutcome::StillStuck);
     }
 
     conflicts.branches.remove(&branch.branch);
     conflicts.write_to_file(conflicts_path)?;
 
-    let merge_base = get_merge_base(worktree_path, &branch.branch, onto_branch)?;
+    let merge_base = get_merge_base(worktree_path, &branch.branch, target_commit)?
+        .ok_or_else(|| anyhow!("Branch has no common ancestor with the target"))?;
+
+    let mut target_commit_list = get_commit_list(worktree_path, &merge_base, target_commit)?;
+
+    // Don't go past the newest commit that passes the quality check. That is
+    // for commits on the target branch, so it isn't used for branches that
+    // have their own target.
+    if let Some(checker) = context.quality_checker.filter(|_| !own_target) {
+        let good = checker
+            .newest_good(&target_commit_list)?
+            .unwrap_or(target_commit_list.len());
+        if good > 0 {
+            reportln!(
+                "{}",
+                format!(
+                    "    - Not rebasing onto the newest {} commit(s) because they didn't pass the quality check",
+                    good
+                )
+                .yellow()
+            );
+            target_commit_list.drain(..good);
+        }
+    }
+    let target_commit = target_commit_list
+        .first()
+        .map_or(merge_base.as_str(), String::as_str);
+
+    if !target_commit_list.is_empty()
+        && rebased_recently(
+            &branch.branch,
+            target_commit_list.len(),
+            conflicts,
+            context.options,
+        )
+    {
+        reportln!(
+            "    - Skipping rebase because it was rebased recently and {} has only moved on by {} commit(s)",
+            onto_branch.bold(),
+            target_commit_list.len()
+        );
+        return Ok(BranchOutcome::RebasedRecently);
+    }
 
-    let target_commit_list = get_commit_list(worktree_path, &merge_base, onto_branch)?;
+    let autosquash = context.options.autosquash
+        && has_autosquash_commits(worktree_path, &merge_base, &branch.branch)?;
 
-    if target_commit_list.is_empty() {
-        eprintln!("    - No rebase necessary");
-        return Ok(());
+    if target_commit_list.is_empty() && !autosquash {
+        reportln!("    - No rebase necessary");
+        return Ok(BranchOutcome::UpToDate);
+    }
+
+    // `git rebase` drops these itself but `git replay` doesn't.
+    let applied_commits = get_applied_commits(worktree_path, target_commit, &branch.branch)?;
+
+    let sign =
+        context
+            .options
+            .signing_policy
+            .should_sign(worktree_path, &merge_base, &branch.branch)?;
+
+    if sign && !context.signing_available {
+        reportln!(
+            "{}",
+            "    - Skipping rebase because commits need to be signed but signing is not available"
+                .yellow()
+        );
+        return Ok(BranchOutcome::SigningUnavailable);
+    }
+
+    // If it isn't checked out anywhere we can usually rebase it without
+    // checking anything out, which is much faster in big repos. If that
+    // doesn't work (e.g. because of conflicts) do it the normal way.
+    if context.replay != Replay::Unavailable
+        && branch.worktree.is_none()
+        && !sign
+        && !autosquash
+        && applied_commits.is_empty()
+    {
+        let onto = &target_commit_list[0];
+        if let Some(new_commit) =
+            replay_branch(worktree_path, context.replay, &branch.branch, onto)?
+        {
+            if !move_branch(
+                worktree_path,
+                &branch.branch,
+                &new_commit,
+                &branch_commit,
+                &reflog_action(context.run_id, RebaseKind::Full, onto),
+            )? {
+                return Ok(changed_during_run());
+            }
+            reportln!("{}", "    - Success!".green());
+            return Ok(BranchOutcome::Rebased);
+        }
     }
 
     // The worktree we will use for the rebase. If it is already checked out
@@ -260,41 +854,121 @@ fn rebase_branch(
         // It's checked out in a worktree somewhere.
         &worktree.path
     } else {
-        // It isn't checked out anywhere; switch to it in our temporary worktree.
-        switch_to_branch(&branch.branch, worktree_path)?;
+        // It isn't checked out anywhere. Rebase it detached in our temporary
+        // worktree and move the branch when we're done, so that we don't
+        // clobber any commits made to it in the meantime.
+        git_in_worktree(&["switch", "--detach", &branch_commit], worktree_path)?;
         worktree_path
     };
 
+    let flags = RebaseFlags {
+        sign,
+        autosquash: false,
+        resolve_submodules: context.options.resolve_submodules,
+    };
+
+    // The reflog message for the last rebase that succeeded, for when we move
+    // the branch ourselves.
+    let mut reflog_message = None;
+    // The commit that the last successful rebase was onto.
+    let mut rebased_onto = None;
+
+    // Squash the fixups in place first. That way the conflict detection below
+    // doesn't have to worry about commits being reordered. If squashing causes
+    // conflicts by itself then just rebase the branch as it is.
+    let mut squashed = false;
+    if autosquash {
+        reportln!("    - Squashing fixup commits");
+        let action = reflog_action(context.run_id, RebaseKind::Squash, &merge_base);
+        let result = attempt_rebase(
+            git_common_dir,
+            rebase_worktree_path,
+            &merge_base,
+            &action,
+            RebaseFlags {
+                autosquash: true,
+                ..flags
+            },
+        )?;
+        match result {
+            RebaseResult::Success => {
+                squashed = true;
+                reflog_message = Some(action);
+            }
+            RebaseResult::Conflict => {
+                reportln!(
+                    "{}",
+                    "    - Conflicts while squashing; rebasing without squashing".yellow()
+                );
+            }
+        }
+    }
+
+    if target_commit_list.is_empty() {
+        if finish_rebase(branch, &branch_commit, reflog_message.as_deref(), context)?.is_none() {
+            return Ok(changed_during_run());
+        }
+        reportln!("    - No rebase necessary");
+        return Ok(if squashed {
+            BranchOutcome::Rebased
+        } else {
+            BranchOutcome::UpToDate
+        });
+    }
+
     let mut stopped_by_conflicts = false;
 
-    if slow_conflict_detection {
-        for target_commit in target_commit_list {
-            eprintln!("    - Rebasing onto {}", target_commit.bold());
+    if context.options.slow_conflict_detection {
+        for (index, target_commit) in target_commit_list.iter().enumerate() {
+            reportln!("    - Rebasing onto {}", target_commit.bold());
 
-            let result = attempt_rebase(git_common_dir, rebase_worktree_path, &target_commit)?;
+            let kind = if index == 0 {
+                RebaseKind::Full
+            } else {
+                RebaseKind::Partial
+            };
+            let action = reflog_action(context.run_id, kind, target_commit);
+            let result = attempt_rebase(
+                git_common_dir,
+                rebase_worktree_path,
+                target_commit,
+                &action,
+                flags,
+            )?;
             match result {
                 RebaseResult::Success => {
-                    eprintln!("{}", "    - Success!".green());
+                    reportln!("{}", "    - Success!".green());
+                    reflog_message = Some(action);
+                    rebased_onto = Some(target_commit.as_str());
                     break;
                 }
                 RebaseResult::Conflict => {
-                    eprintln!("{}", "    - Conflicts...".yellow());
+                    reportln!("{}", "    - Conflicts...".yellow());
                     stopped_by_conflicts = true;
                     continue;
                 }
             }
         }
     } else {
-        let result = attempt_rebase(git_common_dir, rebase_worktree_path, &target_commit_list[0])?;
+        let action = reflog_action(context.run_id, RebaseKind::Full, &target_commit_list[0]);
+        let result = attempt_rebase(
+            git_common_dir,
+            rebase_worktree_path,
+            &target_commit_list[0],
+            &action,
+            flags,
+        )?;
         match result {
             RebaseResult::Success => {
-                eprintln!("{}", "    - Success!".green());
+                reportln!("{}", "    - Success!".green());
+                reflog_message = Some(action);
+                rebased_onto = Some(target_commit_list[0].as_str());
             }
             RebaseResult::Conflict => {
-                eprintln!("{}", "    - Conflicts...".yellow());
+                reportln!("{}", "    - Conflicts...".yellow());
                 stopped_by_conflicts = true;
 
-                eprintln!("    - Finding first conflict...");
+                reportln!("    - Finding first conflict...");
 
                 // Save the current checkout state.
                 let old_location = get_current_branch_or_commit(rebase_worktree_path)?;
@@ -302,8 +976,9 @@ fn rebase_branch(
                 let num_nonconflicting_commits = count_nonconflicting_commits_via_rebase(
                     git_common_dir,
                     rebase_worktree_path,
-                    &branch.branch,
-                    onto_branch,
+                    &branch_commit,
+                    target_commit,
+                    flags.resolve_submodules,
                 )?;
 
                 // Restore the previous state.
@@ -319,17 +994,26 @@ fn rebase_branch(
                     // Then see which commit failed. Finally try to rebase
                     // the branch onto master at the last commit that succeeded.
 
+                    let action = reflog_action(
+                        context.run_id,
+                        RebaseKind::Partial,
+                        last_nonconflicting_commit,
+                    );
                     let result = attempt_rebase(
                         git_common_dir,
                         rebase_worktree_path,
                         last_nonconflicting_commit,
+                        &action,
+                        flags,
                     )?;
                     match result {
                         RebaseResult::Success => {
-                            eprintln!("{}", "    - Success!".green());
+                            reportln!("{}", "    - Success!".green());
+                            reflog_message = Some(action);
+                            rebased_onto = Some(last_nonconflicting_commit.as_str());
                         }
                         RebaseResult::Conflict => {
-                            eprintln!("{}", "    - Conflicts...".yellow());
+                            reportln!("{}", "    - Conflicts...".yellow());
                         }
                     }
                 }
@@ -337,26 +1021,94 @@ fn rebase_branch(
         }
     }
 
-    // Switch to the branch so that we don't leave references to unneeded commits
-    // around, and detach otherwise we may prevent people checking it out.
-    git(&["switch", "--detach", &branch.branch], worktree_path)?;
+    let Some(new_branch_commit) =
+        finish_rebase(branch, &branch_commit, reflog_message.as_deref(), context)?
+    else {
+        return Ok(changed_during_run());
+    };
+
+    // Git drops these itself, but tell the user so they don't wonder where
+    // their commits went. If there were conflicts we may not have got all
+    // the way to the target.
+    if let Some(rebased_onto) = rebased_onto {
+        let dropped_commits = get_applied_commits(worktree_path, rebased_onto, &branch_commit)?;
+        if !dropped_commits.is_empty() {
+            reportln!(
+                "    - Dropped {} commit(s) already on {}:",
+                dropped_commits.len(),
+                onto_branch.bold()
+            );
+            for (hash, subject) in &dropped_commits {
+                reportln!("        {} {}", hash.dimmed(), subject);
+            }
+        }
+    }
 
     if stopped_by_conflicts {
-        eprintln!(
+        reportln!(
             "{}",
             "    - Rebase stunted by conflicts. Rebase manually.".yellow()
         );
 
-        // Get the commit again because it will have changed (probably).
-        let new_branch_commit = get_commit_hash(worktree_path, &branch.branch)?;
-
         conflicts
             .branches
             .insert(branch.branch.clone(), new_branch_commit);
         conflicts.write_to_file(conflicts_path)?;
+
+        return Ok(BranchOutcome::Conflicts);
     }
 
-    Ok(())
+    Ok(BranchOutcome::Rebased)
+}
+
+/// Called once `branch`, which was at `branch_commit`, has been rebased as
+/// far as it will go. If that was done in our worktree the branch is moved to
+/// the result, with `reflog_message` (which is `None` if it wasn't changed).
+/// Returns the commit the branch is now at, or `None` if it was changed by
+/// someone else while we were rebasing it.
+fn finish_rebase(
+    branch: &BranchInfo,
+    branch_commit: &str,
+    reflog_message: Option<&str>,
+    context: &RebaseContext,
+) -> Result<Option<String>> {
+    let worktree_path = context.worktree_path;
+
+    // If it is checked out elsewhere `git rebase` has already updated it, and
+    // nobody could commit to it while that was in progress.
+    let new_commit = if branch.worktree.is_none() {
+        let new_commit = get_commit_hash(worktree_path, "HEAD")?;
+        move_branch(
+            worktree_path,
+            &branch.branch,
+            &new_commit,
+            branch_commit,
+            reflog_message.unwrap_or("autorebase"),
+        )?
+        .then_some(new_commit)
+    } else {
+        Some(get_commit_hash(worktree_path, &branch.branch)?)
+    };
+
+    // Switch to the branch so that we don't leave references to unneeded commits
+    // around, and detach otherwise we may prevent people checking it out. If
+    // it was changed it may have been deleted, so use where it was.
+    let switch_to = if new_commit.is_some() {
+        branch.branch.as_str()
+    } else {
+        branch_commit
+    };
+    git_in_worktree(&["switch", "--detach", switch_to], worktree_path)?;
+
+    Ok(new_commit)
+}
+
+fn changed_during_run() -> BranchOutcome {
+    reportln!(
+        "{}",
+        "    - Branch was changed while we were rebasing it; leaving it alone".yellow()
+    );
+    BranchOutcome::ChangedDuringRun
 }
 
 /// Utility function to get the worktree dir for the given directory.
@@ -381,14 +1133,6 @@ pub fn get_git_common_dir(for_path: &Path) -> Result<PathBuf> {
     Ok(PathBuf::from(output))
 }
 
-fn create_scratch_worktree(working_dir: &Path, worktree_path: &Path) -> Result<()> {
-    let worktree_path = worktree_path
-        .to_str()
-        .ok_or_else(|| anyhow!("worktree path is not unicode"))?;
-    git(&["worktree", "add", "--detach", worktree_path], working_dir)?;
-    Ok(())
-}
-
 #[derive(Debug)]
 struct WorktreeInfo {
     // Path to the worktree.
@@ -402,72 +1146,49 @@ struct BranchInfo {
     branch: String,
     upstream: Option<String>,
     worktree: Option<WorktreeInfo>,
+    // Author email of the tip commit, without the `<>`.
+    author_email: String,
+    // Author date of the tip commit in seconds since the Unix epoch. We use
+    // the author date rather than the committer date because rebasing
+    // (including by us) changes the committer date.
+    author_date: i64,
+    // Its `branch.<name>.*` settings.
+    config: BranchConfig,
 }
 
 fn get_branches(working_dir: &Path) -> Result<Vec<BranchInfo>> {
-    use std::str;
-
     // TODO: Config system to allow specifying the branches? Maybe allow adding/removing them?
     // Store config in `.git/autorebase/autorebase.toml` or `autorebase.toml`?
 
-    let output = git(
-        &[
-            "for-each-ref",
-            "--format=%(refname:short)%00%(upstream:short)%00%(worktreepath)",
-            "refs/heads",
-        ],
-        working_dir,
-    )?
-    .stdout;
-    let branches = output
-        .split(|c| *c == b'\n')
-        .filter(|line| !line.is_empty())
-        .map(|line| {
-            let parts: Vec<&[u8]> = line.split(|c| *c == 0).collect();
-            if parts.len() != 3 {
-                bail!(
-                    "for-each-ref parse error, got {} parts, expected 3",
-                    parts.len()
-                );
-            }
-
-            let branch = str::from_utf8(parts[0])?.to_owned();
+    let mut configs = branch_configs(working_dir)?;
 
-            let upstream = if parts[1].is_empty() {
-                None
-            } else {
-                Some(str::from_utf8(parts[1])?.to_owned())
-            };
-
-            let worktree = if parts[2].is_empty() {
-                None
-            } else {
-                let path = str::from_utf8(parts[2])?;
-                let path = PathBuf::from(path);
+    let branches = backend()
+        .branches(working_dir)?
+        .into_iter()
+        // This temporary branch should have been deleted but filter it out just in case something went wrong.
+        .filter(|branch| branch.name != TEMPORARY_BRANCH_NAME)
+        .map(|branch| BranchInfo {
+            config: configs.remove(&branch.name).unwrap_or_default(),
+            branch: branch.name,
+            upstream: branch.upstream,
+            worktree: branch.worktree.map(|path| {
                 let clean = is_clean(&path);
-                Some(WorktreeInfo { path, clean })
-            };
-
-            Ok(BranchInfo {
-                branch,
-                upstream,
-                worktree,
-            })
+                WorktreeInfo { path, clean }
+            }),
+            author_email: branch.author_email,
+            author_date: branch.author_date,
         })
-        // This temporary branch should have been deleted but filter it out just in case something went wrong.
-        .filter(|branch| !matches!(branch, Ok(b) if b.branch == TEMPORARY_BRANCH_NAME))
-        .collect::<Result<_, _>>()?;
+        .collect();
     Ok(branches)
 }
 
-fn get_merge_base(working_dir: &Path, a: &str, b: &str) -> Result<String> {
-    let output = git(&["merge-base", a, b], working_dir)?.stdout;
-    let output = std::str::from_utf8(output.trim_ascii_whitespace())?;
-    Ok(output.to_owned())
+/// The best common ancestor of `a` and `b`, or `None` if they don't have one.
+fn get_merge_base(working_dir: &Path, a: &str, b: &str) -> Result<Option<String>> {
+    Ok(backend().merge_base(working_dir, a, b)?)
 }
 
 fn switch_to_branch(branch: &str, working_dir: &Path) -> Result<()> {
-    git(&["switch", branch], working_dir)?;
+    git_in_worktree(&["switch", branch], working_dir)?;
     Ok(())
 }
 
@@ -503,16 +1224,69 @@ enum RebaseResult {
     Conflict,
 }
 
+/// Options for `attempt_rebase()`.
+#[derive(Debug, Clone, Copy)]
+struct RebaseFlags {
+    /// Sign the rebased commits. If false signing is disabled.
+    sign: bool,
+    /// Squash `fixup!` and `squash!` commits (non-interactively).
+    autosquash: bool,
+    /// Resolve conflicts between submodule commits if one is newer.
+    resolve_submodules: bool,
+}
+
 // Attempt to rebase the current branch in the `worktree_path` onto the `onto`
-// branch. `git_common_dir` points to the main `.git` directory.
+// commit. `reflog_action` (from `reflog_action()`) is recorded in the reflog.
+// `git_common_dir` points to the main `.git` directory.
 // `worktree_path` points to the worktree, which may be the same (`/foo`)
 // or may be another path. If we are using our private worktree it will be
 // something like `/foo/.git/autorebase/autorebase_worktree`.
-fn attempt_rebase(git_common_dir: &Path, worktree_path: &Path, onto: &str) -> Result<RebaseResult> {
-    let rebase_ok = git(&["rebase", onto], worktree_path);
-    if rebase_ok.is_ok() {
-        return Ok(RebaseResult::Success);
+fn attempt_rebase(
+    git_common_dir: &Path,
+    worktree_path: &Path,
+    onto: &str,
+    reflog_action: &str,
+    flags: RebaseFlags,
+) -> Result<RebaseResult> {
+    // Commits whose changes are already on `onto` are dropped. Git does that
+    // anyway for non-interactive rebases but interactive ones would stop.
+    let mut args = vec!["rebase", rebase_signing_flag(flags.sign), "--empty=drop"];
+    if flags.autosquash {
+        // Autosquash only works for interactive rebases, so make it
+        // non-interactive by accepting the todo list as it is.
+        args.extend(["--interactive", "--autosquash"]);
     }
+    args.push(onto);
+    let mut env = vec![
+        ("GIT_SEQUENCE_EDITOR", ":"),
+        // `squash!` commits open an editor for the combined message. Keep it
+        // as it is, even if `--git-env` sets an editor.
+        ("GIT_EDITOR", ":"),
+        ("GIT_REFLOG_ACTION", reflog_action),
+    ];
+    env.extend(worktree_env(worktree_path));
+    let mut progress = ProgressLine::new("    - ");
+    let rebase_result = git_with_progress(&args, worktree_path, &env, &mut |line| {
+        if let Some(line) = rebase_progress(line) {
+            progress.update(&line);
+        }
+    });
+    drop(progress);
+
+    let error = match rebase_result {
+        Ok(_) => return Ok(RebaseResult::Success),
+        Err(e) => e,
+    };
+    let error = match continue_past_submodule_conflicts(
+        git_common_dir,
+        worktree_path,
+        error,
+        flags.resolve_submodules,
+        &env,
+    )? {
+        None => return Ok(RebaseResult::Success),
+        Some(e) => e,
+    };
 
     // We may need to abort if the rebase is still in progress. Git checks
     // the rebase status like this:
@@ -523,14 +1297,129 @@ fn attempt_rebase(git_common_dir: &Path, worktree_path: &Path, onto: &str) -> Re
 
     let worktree = get_worktree_name(worktree_path)?;
 
-    if is_rebasing(git_common_dir, worktree.as_deref()) {
+    let rebasing = is_rebasing(git_common_dir, worktree.as_deref());
+    if rebasing {
+        report_submodule_conflicts(worktree_path, flags.resolve_submodules)?;
         // Abort the rebase.
-        git(&["rebase", "--abort"], worktree_path)?;
+        git_in_worktree(&["rebase", "--abort"], worktree_path)?;
+    }
+
+    if !is_conflict(&error, rebasing) {
+        return Err(error.into());
     }
 
     Ok(RebaseResult::Conflict)
 }
 
+/// Which kind of `git replay` we have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Replay {
+    /// It was added in Git 2.44.
+    Unavailable,
+    /// It only prints the ref updates.
+    PrintsRefs,
+    /// Since Git 2.52 it updates the refs itself unless given
+    /// `--ref-action=print`.
+    UpdatesRefs,
+}
+
+impl Replay {
+    fn for_version(git_version: &[i32]) -> Self {
+        if git_version >= &[2, 52] {
+            Self::UpdatesRefs
+        } else if git_version >= &[2, 44] {
+            Self::PrintsRefs
+        } else {
+            Self::Unavailable
+        }
+    }
+}
+
+/// Rebase `branch` onto `onto` using `git replay`, which works without a
+/// worktree. Returns the rebased commit, or `None` if it couldn't be done
+/// that way, e.g. because of conflicts or merge commits. The branch itself
+/// isn't changed; the caller moves it with `move_branch()` so that it isn't
+/// overwritten if it has changed.
+fn replay_branch(
+    working_dir: &Path,
+    replay: Replay,
+    branch: &str,
+    onto: &str,
+) -> Result<Option<String>> {
+    let branch_ref = format!("refs/heads/{}", branch);
+    let range = format!("{}..{}", onto, branch_ref);
+    let mut args = vec!["replay"];
+    if replay == Replay::UpdatesRefs {
+        args.push("--ref-action=print");
+    }
+    args.extend(["--onto", onto, &range]);
+    let output = match git(&args, working_dir) {
+        Ok(output) => output.stdout,
+        Err(e) => match e.kind() {
+            ErrorKind::TimedOut | ErrorKind::Cancelled => return Err(e.into()),
+            _ => return Ok(None),
+        },
+    };
+    let output = String::from_utf8(output)?;
+
+    // It prints commands for `git update-ref --stdin` like
+    // `update <ref> <new> <old>`.
+    Ok(output.lines().find_map(
+        |line| match line.split(' ').collect::<Vec<_>>().as_slice() {
+            ["update", name, new, _] if *name == branch_ref => Some(new.to_string()),
+            _ => None,
+        },
+    ))
+}
+
+/// Point `branch` at `new_commit`, as long as it is still at `old_commit`.
+/// Returns false if it has changed since we looked at it, e.g. because
+/// someone committed to it from another worktree, in which case it is left
+/// alone.
+fn move_branch(
+    working_dir: &Path,
+    branch: &str,
+    new_commit: &str,
+    old_commit: &str,
+    message: &str,
+) -> Result<bool> {
+    if new_commit == old_commit {
+        // There's nothing to do, but it may have been moved or deleted.
+        return match backend().commit_hash(working_dir, &format!("refs/heads/{}", branch)) {
+            Ok(commit) => Ok(commit == old_commit),
+            Err(e) if e.kind() == ErrorKind::UnknownRevision => Ok(false),
+            Err(e) => Err(e.into()),
+        };
+    }
+    match backend().update_ref(
+        working_dir,
+        &format!("refs/heads/{}", branch),
+        new_commit,
+        Some(old_commit),
+        message,
+    ) {
+        Ok(()) => Ok(true),
+        // It was deleted if it is an unknown revision.
+        Err(e) if matches!(e.kind(), ErrorKind::RefChanged | ErrorKind::UnknownRevision) => {
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Did a rebase that failed with `error` fail due to conflicts? If not it
+/// was something environmental (a lock file, signing etc.) that has nothing
+/// to do with the commits, so we shouldn't remember it as a conflict.
+fn is_conflict(error: &git_commands::Error, rebasing: bool) -> bool {
+    match error.kind() {
+        ErrorKind::MergeConflict => true,
+        // We don't recognise the message, but if the rebase stopped partway
+        // through it is most likely a conflict.
+        ErrorKind::Other => rebasing,
+        _ => false,
+    }
+}
+
 const TEMPORARY_BRANCH_NAME: &str = "autorebase_tmp_safe_to_delete";
 
 /// Create a temporary branch at master (`onto`), then try to rebase it ont
@@ -543,30 +1432,60 @@ fn count_nonconflicting_commits_via_rebase(
     worktree_path: &Path,
     branch: &str,
     onto: &str,
+    resolve_submodules: bool,
 ) -> Result<usize> {
     // Create a temporary branch at master. If it already exists (e.g. because
     // a previous command failed) just reset it to here.
-    git(
+    git_in_worktree(
         &["switch", "--force-create", TEMPORARY_BRANCH_NAME, onto],
         worktree_path,
     )?;
 
     // Rebase onto branch.
     // Disable code signing for this rebase because it is very slow and
-    // we don't need it.
-    let rebase_ok = git(
-        &["-c", "commit.gpgsign=false", "rebase", branch],
+    // we don't need it. Keep commits that are already on `branch` (e.g.
+    // because some of it was cherry-picked onto master), otherwise they
+    // wouldn't be counted and we'd pick the wrong commit.
+    let rebase_result = git_in_worktree(
+        &[
+            "-c",
+            "commit.gpgsign=false",
+            "rebase",
+            "--reapply-cherry-picks",
+            "--empty=keep",
+            branch,
+        ],
         worktree_path,
     );
-    if rebase_ok.is_ok() {
+    let error = match rebase_result {
+        Ok(_) => None,
+        Err(e) => continue_past_submodule_conflicts(
+            git_common_dir,
+            worktree_path,
+            e,
+            resolve_submodules,
+            worktree_env(worktree_path),
+        )?,
+    };
+    let error = match error {
         // Rebase worked one way but not in the other. Bit weird. This probably
         // shouldn't happen normally but we'll just give up.
-        return Ok(0);
-    }
+        None => return Ok(0),
+        Some(e) => e,
+    };
 
     let worktree = get_worktree_name(worktree_path)?;
 
-    if !is_rebasing(git_common_dir, worktree.as_deref()) {
+    let rebasing = is_rebasing(git_common_dir, worktree.as_deref());
+
+    if !is_conflict(&error, rebasing) {
+        if rebasing {
+            git_in_worktree(&["rebase", "--abort"], worktree_path)?;
+        }
+        return Err(error.into());
+    }
+
+    if !rebasing {
         // Error - it should be rebasing!
         bail!("Rebase failed but repo is not rebasing.");
     }
@@ -575,11 +1494,11 @@ fn count_nonconflicting_commits_via_rebase(
     let commit_list = get_commit_list(worktree_path, branch, "HEAD")?;
 
     // Abort the rebase.
-    git(&["rebase", "--abort"], worktree_path)?;
+    git_in_worktree(&["rebase", "--abort"], worktree_path)?;
 
     // Delete the branch and checkout master (detached) otherwise we risk
     // keeping commits around.
-    git(&["switch", "--detach", onto], worktree_path)?;
+    git_in_worktree(&["switch", "--detach", onto], worktree_path)?;
 
     git(
         &["branch", "--delete", "--force", TEMPORARY_BRANCH_NAME],
@@ -589,27 +1508,56 @@ fn count_nonconflicting_commits_via_rebase(
     Ok(commit_list.len())
 }
 
-/// Get the list of commits from `from` to `to`. The list includes `to` but not
-/// `from`.
-fn get_commit_list(working_dir: &Path, from: &str, to: &str) -> Result<Vec<String>> {
+/// Are there any `fixup!`, `squash!` or `amend!` commits from `from` to `to`?
+fn has_autosquash_commits(working_dir: &Path, from: &str, to: &str) -> Result<bool> {
     let output = git(
         &[
             "--no-pager",
             "log",
-            "--format=%H",
+            "--format=%s",
             &format!("{}..{}", from, to),
         ],
         working_dir,
     )?
     .stdout;
     let output = String::from_utf8(output)?;
-    Ok(output.lines().map(ToOwned::to_owned).collect())
+    Ok(output.lines().any(|subject| {
+        ["fixup! ", "squash! ", "amend! "]
+            .iter()
+            .any(|prefix| subject.starts_with(prefix))
+    }))
+}
+
+/// Get the commits on `branch` whose changes are already on `upstream`, by
+/// patch ID (see `git cherry`). Returns the abbreviated hash and subject of
+/// each, oldest first.
+fn get_applied_commits(
+    working_dir: &Path,
+    upstream: &str,
+    branch: &str,
+) -> Result<Vec<(String, String)>> {
+    let output = git(&["cherry", "-v", "--abbrev", upstream, branch], working_dir)?.stdout;
+    let output = String::from_utf8(output)?;
+    Ok(output
+        .lines()
+        .filter_map(|line| line.strip_prefix("- "))
+        .map(|line| match line.split_once(' ') {
+            Some((hash, subject)) => (hash.to_owned(), subject.to_owned()),
+            None => (line.to_owned(), String::new()),
+        })
+        .collect())
+}
+
+/// Get the list of commits from `from` to `to`. The list includes `to` but not
+/// `from`.
+fn get_commit_list(working_dir: &Path, from: &str, to: &str) -> Result<Vec<String>> {
+    Ok(backend().commit_list(working_dir, from, to)?)
 }
 
 /// Return the Git version like [2, 3, 30]. Really annoyingly the version sometimes
 /// includes text, for example 2.31.1.windows.1 (yes really). We will just convert
 /// unparsable values to -1. Ugly but they started it.
-fn git_version() -> Result<Vec<i32>> {
+pub fn git_version() -> Result<Vec<i32>> {
     // The output of `git version` is guaranteed to be stable, though it has a stupid
     // "git version " string at the start.
     let output = git_cwd(&["version"])?.stdout;
@@ -637,26 +1585,21 @@ enum BranchOrCommit {
 /// name if we are on an unborn branch.
 ///
 fn get_current_branch(working_dir: &Path) -> Result<Option<String>> {
-    let output = Command::new("git")
-        .current_dir(working_dir)
-        .args(["symbolic-ref", "--quiet", "--short", "HEAD"])
-        .output()?;
-
-    if output.status.success() {
-        let branch = std::str::from_utf8(output.stdout.trim_ascii_whitespace())?;
-        Ok(Some(branch.to_owned()))
-    } else {
-        Ok(None)
-    }
+    let output = match git(&["symbolic-ref", "--quiet", "--short", "HEAD"], working_dir) {
+        Ok(output) => output,
+        // Not on a branch.
+        Err(Error::Process(e)) if e.output().status.code() == Some(1) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let branch = std::str::from_utf8(output.stdout.trim_ascii_whitespace())?;
+    Ok(Some(branch.to_owned()))
 }
 
-/// Use `git rev-parse HEAD` to return the current commit hash. It will
+/// Return the commit hash for `branch`, like `git rev-parse`. It will
 /// return an error if we are on an unborn branch. That's an error for us though
 /// so we don't have to treat that case specially.
 fn get_commit_hash(working_dir: &Path, branch: &str) -> Result<String> {
-    let commit = git(&["rev-parse", branch], working_dir)?.stdout;
-    let commit = std::str::from_utf8(commit.trim_ascii_whitespace())?;
-    Ok(commit.to_owned())
+    Ok(backend().commit_hash(working_dir, branch)?)
 }
 
 fn get_current_branch_or_commit(working_dir: &Path) -> Result<BranchOrCommit> {
@@ -671,10 +1614,10 @@ fn get_current_branch_or_commit(working_dir: &Path) -> Result<BranchOrCommit> {
 fn switch_to_branch_or_commit(working_dir: &Path, branch_or_commit: &BranchOrCommit) -> Result<()> {
     match branch_or_commit {
         BranchOrCommit::Branch(ref branch) => {
-            git(&["switch", branch], working_dir)?;
+            git_in_worktree(&["switch", branch], working_dir)?;
         }
         BranchOrCommit::Commit(ref commit) => {
-            git(&["switch", "--detach", commit], working_dir)?;
+            git_in_worktree(&["switch", "--detach", commit], working_dir)?;
         }
     }
     Ok(())
@@ -696,10 +1639,15 @@ fn get_worktree_name(worktree_path: &Path) -> Result<Option<String>> {
 
     if let Some(path_str) = gitdir.trim().strip_prefix("gitdir: ") {
         let path = Path::new(path_str);
+        // Linked worktrees point to `.git/worktrees/<name>`. Submodules also
+        // have a `.git` file, pointing to `.git/modules/<name>`, but those are
+        // main worktrees.
+        if path.parent().and_then(Path::file_name) != Some("worktrees".as_ref()) {
+            return Ok(None);
+        }
         path.components()
-            .last()
+            .next_back()
             .ok_or_else(|| anyhow!("Invalid worktree/.git path: '{}'", path_str))
-            .map(Into::into)
             .and_then(|component| match component {
                 Component::Normal(s) => s
                     .to_str()
@@ -0,0 +1,334 @@
+use anyhow::{bail, Result};
+use colored::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Advisory lock to stop two instances of autorebase running on the same repo
+// at the same time, since they would both be using the same scratch worktree
+// and `conflicts.toml`.
+//
+// The lock file records who holds it so that we can detect locks left behind
+// by a crashed process. A lock is considered stale if it was created on this
+// host by a process that no longer exists, or if it is older than
+// `STALE_LOCK_AGE` (in which case we assume something has gone very wrong).
+//
+// The lock is written to a temporary file and then linked into place, so a
+// lock file that can't be read usually wasn't written by us. It may be from
+// another version, or damaged by a crash, or (on filesystems without hard
+// links, where it is created in place) still being written, so it is left
+// alone for `UNREADABLE_LOCK_AGE` before being removed in case it is about to
+// be replaced.
+//
+// Two processes may find the same stale lock. To stop one of them removing a
+// new lock that the other created after removing the stale one, stale locks
+// are only removed while holding a second lock file, and only if they are
+// still stale.
+
+/// Locks older than this are assumed to be stale, wherever they came from.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Lock files we can't read are assumed to be stale once they are this old.
+/// This is also how long the lock for removing stale locks can be held.
+const UNREADABLE_LOCK_AGE: Duration = Duration::from_secs(10);
+
+/// How often to check the lock when waiting for it.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LockInfo {
+    /// Process ID of the lock holder.
+    pid: u32,
+    /// Hostname of the machine the lock holder is running on.
+    hostname: String,
+    /// When the lock was taken, in seconds since the Unix epoch.
+    created: u64,
+}
+
+impl LockInfo {
+    fn for_this_process() -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: this_hostname(),
+            created: now_secs(),
+        }
+    }
+
+    fn age(&self) -> Duration {
+        Duration::from_secs(now_secs().saturating_sub(self.created))
+    }
+
+    fn is_stale(&self) -> bool {
+        if self.age() > STALE_LOCK_AGE {
+            return true;
+        }
+        // We can only check if the process is alive if it is on this machine.
+        self.hostname == this_hostname() && !process_exists(self.pid)
+    }
+}
+
+/// A held repository lock. The lock is released when this is dropped.
+#[derive(Debug)]
+pub struct RepoLock {
+    path: PathBuf,
+    /// What we wrote to the lock file, so we can tell if someone else has
+    /// taken it over.
+    contents: String,
+}
+
+impl RepoLock {
+    /// Take the lock for the repo whose main `.git` directory is `git_common_dir`.
+    /// If it is held by another live process then either fail or, if `wait`
+    /// is true, wait until it is released.
+    pub fn acquire(git_common_dir: &Path, wait: bool) -> Result<Self> {
+        let autorebase_dir = git_common_dir.join("autorebase");
+        fs::create_dir_all(&autorebase_dir)?;
+        let path = autorebase_dir.join("autorebase.lock");
+
+        let mut waiting = false;
+
+        loop {
+            match Self::try_create(&path) {
+                Ok(contents) => {
+                    if waiting {
+                        reportln!(
+                            "\r{}",
+                            "• Waiting for another autorebase to finish...".green()
+                        );
+                    }
+                    return Ok(Self { path, contents });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            // Somebody else has it.
+            match read_lock(&path)? {
+                // It has been deleted since we tried to create it.
+                LockState::Missing => {}
+                LockState::Held(info) => {
+                    if !wait {
+                        let holder = match info {
+                            Some(info) => format!(
+                                "pid {} on {}, started {}s ago",
+                                info.pid,
+                                info.hostname,
+                                info.age().as_secs()
+                            ),
+                            None => "its lock file can't be read yet".to_owned(),
+                        };
+                        bail!(
+                            "Another autorebase is running ({}). \
+                             Use --wait to wait for it to finish, or delete '{}' if you are \
+                             sure it is not running.",
+                            holder,
+                            path.display(),
+                        );
+                    }
+                    if !waiting {
+                        report!(
+                            "{}",
+                            "• Waiting for another autorebase to finish...".yellow()
+                        );
+                        waiting = true;
+                    }
+                    if git_commands::is_cancelled() {
+                        return Err(git_commands::Error::Cancelled.into());
+                    }
+                    thread::sleep(LOCK_POLL_INTERVAL);
+                }
+                LockState::Stale => remove_stale_lock(&path)?,
+            }
+        }
+    }
+
+    /// Atomically create the lock file with our details in it, and return
+    /// them; this fails with `AlreadyExists` if someone else holds the lock.
+    fn try_create(path: &Path) -> io::Result<String> {
+        // Unique within this process too, in case several threads are
+        // taking locks.
+        static TEMP_COUNTER: AtomicU32 = AtomicU32::new(0);
+        let temp_path = path.with_extension(format!(
+            "lock.{}.{}.tmp",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let contents = toml::to_string(&LockInfo::for_this_process()).map_err(io::Error::other)?;
+        fs::write(&temp_path, &contents)?;
+        // Unlike renaming, this fails if the lock file exists.
+        let result = match fs::hard_link(&temp_path, path) {
+            // Some filesystems (e.g. FAT and some network ones) don't support
+            // hard links. Linux reports that as `EPERM`.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                create_new_with(path, &contents)
+            }
+            result => result,
+        };
+        let _ = fs::remove_file(&temp_path);
+        result.map(|()| contents)
+    }
+}
+
+/// Create the file at `path` containing `contents`, failing with
+/// `AlreadyExists` if it exists.
+fn create_new_with(path: &Path, contents: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    let result = file.write_all(contents.as_bytes());
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// What we found when someone else had the lock.
+#[derive(Debug)]
+enum LockState {
+    /// The lock file no longer exists.
+    Missing,
+    /// It is held by a live process, which we don't know anything about if
+    /// the lock file can't be read.
+    Held(Option<LockInfo>),
+    /// It was left behind by a process that has gone.
+    Stale,
+}
+
+fn read_lock(path: &Path) -> Result<LockState> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(LockState::Missing),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(match toml::from_str::<LockInfo>(&contents) {
+        Ok(info) if info.is_stale() => LockState::Stale,
+        Ok(info) => LockState::Held(Some(info)),
+        Err(_) => match file_age(path) {
+            Ok(age) if age > UNREADABLE_LOCK_AGE => LockState::Stale,
+            Ok(_) => LockState::Held(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => LockState::Missing,
+            Err(e) => return Err(e.into()),
+        },
+    })
+}
+
+/// Remove the lock file at `path` if it is (still) stale. See the comment at
+/// the top.
+fn remove_stale_lock(path: &Path) -> Result<()> {
+    let removal_lock_path = path.with_extension("lock.remove");
+    match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&removal_lock_path)
+    {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            // Somebody else is removing it. If they crashed while doing so
+            // (which is very unlikely) clean up after them.
+            match file_age(&removal_lock_path) {
+                Ok(age) if age > UNREADABLE_LOCK_AGE => {
+                    warn!("Removing stale lock file {:?}", removal_lock_path);
+                    remove_if_exists(&removal_lock_path)?;
+                }
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            thread::sleep(Duration::from_millis(10));
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let result = match read_lock(path) {
+        Ok(LockState::Stale) => {
+            warn!("Removing stale lock file {:?}", path);
+            remove_if_exists(path)
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    };
+    remove_if_exists(&removal_lock_path)?;
+    result
+}
+
+/// How long ago the file at `path` was modified. Zero if that is in the
+/// future.
+fn file_age(path: &Path) -> io::Result<Duration> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default())
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        // If another process decided our lock was stale and took it over,
+        // it's theirs now.
+        match fs::read_to_string(&self.path) {
+            Ok(contents) if contents == self.contents => {}
+            Ok(_) => {
+                warn!(
+                    "Lock file {:?} was taken over by another process; leaving it",
+                    self.path
+                );
+                return;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                warn!("Couldn't read lock file {:?}: {}", self.path, e);
+                return;
+            }
+        }
+        if let Err(e) = remove_if_exists(&self.path) {
+            warn!("Couldn't remove lock file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn this_hostname() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 doesn't send anything but still does the existence and
+    // permission checks. EPERM means it exists but belongs to someone else.
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    // No cheap way to check so assume it is alive and rely on the lock age.
+    true
+}
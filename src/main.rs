@@ -1,21 +1,58 @@
 // Tool to automatically rebase branches.
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use argh::FromArgs;
 
-use autorebase::autorebase;
+use autorebase::{
+    autorebase, autorebase_repos, find_repos, print_rebase_log, print_trace, print_trace_record,
+    rebase_log, replay_trace, resolve, status, AutorebaseOptions, BranchFilter, QualityCheck,
+    ResolveOptions, RunOutcome, SigningPolicy, TargetCommit, WorkspaceOutcome,
+};
 
-use std::env::current_dir;
+use git_commands::{is_interactive, read_trace, CancellationToken, Settings, TraceFile};
+use std::{env::current_dir, path::PathBuf, process::ExitCode, time::Duration};
+
+// Exit codes. If more than one applies the lowest one is used. These are
+// documented in the `--help` output via `error_code` below so keep them in sync.
+
+/// Autorebase failed completely.
+const EXIT_ERROR: u8 = 1;
+/// Some branches couldn't be rebased due to errors, but the others were processed.
+const EXIT_BRANCH_ERRORS: u8 = 2;
+/// Some branches are stuck due to conflicts.
+const EXIT_BRANCHES_STUCK: u8 = 3;
+/// The target branch has an upstream but couldn't be pulled.
+const EXIT_TARGET_NOT_PULLED: u8 = 4;
 
 #[derive(FromArgs)]
 /// Automatically pull the master branch and rebase all branches without
 /// upstreams onto it.
+#[argh(
+    error_code(1, "autorebase failed"),
+    error_code(2, "some branches could not be rebased due to errors"),
+    error_code(3, "some branches are stuck due to conflicts"),
+    error_code(4, "the target branch could not be pulled")
+)]
 struct CliOptions {
     /// the target branch to pull and rebase onto;
     /// defaults to `git config --get init.defaultBranch` or `master` if unset
     #[argh(option)]
     onto: Option<String>,
 
+    /// which commit to rebase onto: `tip` (of the target branch), a tag or
+    /// commit, `notes:<ref>` for the newest commit on the target branch with
+    /// a note in that notes ref (e.g. added by CI), or `before:<date>` for
+    /// the newest one committed before that date; defaults to `tip`
+    #[argh(option, default = "TargetCommit::Tip")]
+    at: TargetCommit,
+
+    /// only rebase onto target commits that pass this check, e.g. in CI:
+    /// `notes:<ref>` for commits with a note in that notes ref, or
+    /// `command:<command>` for commits that the shell command succeeds for,
+    /// given the commit in $AUTOREBASE_COMMIT
+    #[argh(option)]
+    quality_check: Option<QualityCheck>,
+
     /// if there are conflicts, try rebasing commit by commit backwards from the
     /// target, instead of trying to determined the conflicting commit on the
     /// target branch directly
@@ -26,39 +63,378 @@ struct CliOptions {
     #[argh(switch)]
     include_non_local: bool,
 
-    /// branch matching glob, the default is all branches
+    /// only rebase branches matching this glob; may be given more than once.
+    /// Supports `*`, `?`, `[a-z]` and `**`. Patterns starting with `!` exclude
+    /// branches. The default is all branches
+    #[argh(option, long = "match")]
+    match_: Vec<String>,
+
+    /// deprecated alias for --match
+    #[argh(option)]
+    match_branches: Vec<String>,
+
+    /// don't rebase branches matching this glob; may be given more than once
+    #[argh(option)]
+    exclude: Vec<String>,
+
+    /// only rebase branches matching this regex (like --match); may be given
+    /// more than once
+    #[argh(option)]
+    match_regex: Vec<String>,
+
+    /// only rebase branches whose tip commit was authored by you (according to
+    /// `user.email`)
+    #[argh(switch)]
+    mine: bool,
+
+    /// only rebase branches whose tip commit was authored within this many
+    /// days; older branches are assumed to be abandoned
+    #[argh(option)]
+    max_age_days: Option<u64>,
+
+    /// don't rebase branches that autorebase rebased less than this many
+    /// hours ago, to avoid needless rebuilds
+    #[argh(option)]
+    min_interval_hours: Option<u64>,
+
+    /// with --min-interval-hours, rebase anyway if the target has more than
+    /// this many new commits
+    #[argh(option)]
+    min_interval_commits: Option<usize>,
+
+    /// if another autorebase is already running on this repo, wait for it to
+    /// finish instead of exiting with an error
+    #[argh(switch)]
+    wait: bool,
+
+    /// whether to sign rebased commits: `config` (follow commit.gpgsign),
+    /// `always`, `never` or `preserve-if-signed`; defaults to `config`
+    #[argh(option, default = "SigningPolicy::Config")]
+    sign: SigningPolicy,
+
+    /// squash `fixup!` and `squash!` commits into the commits they fix, like
+    /// `git rebase --interactive --autosquash`
+    #[argh(switch)]
+    autosquash: bool,
+
+    /// if a branch and the target both change a submodule's commit, take the
+    /// newer one instead of treating it as a conflict
+    #[argh(switch)]
+    resolve_submodules: bool,
+
+    /// also autorebase the submodules that are checked out
+    #[argh(switch)]
+    recurse_submodules: bool,
+
+    /// only check out this directory in autorebase's worktree (using cone
+    /// mode sparse-checkout); may be given more than once. The default is to
+    /// copy the sparse-checkout settings of the current worktree
+    #[argh(option)]
+    sparse: Vec<String>,
+
+    /// kill git commands (and quality check commands) that take longer than
+    /// this many seconds, e.g. because a hook is waiting for input
+    #[argh(option)]
+    git_timeout: Option<u64>,
+
+    /// set an environment variable for every git command, e.g.
+    /// --git-env GIT_SSH_COMMAND='ssh -o BatchMode=yes'; may be given more
+    /// than once
+    #[argh(option, from_str_fn(parse_env_var))]
+    git_env: Vec<(String, String)>,
+
+    /// autorebase every git repo in this directory or below it instead of the
+    /// current one. Unless --onto is given each repo's target branch is found
+    /// separately
+    #[argh(option)]
+    repos: Option<PathBuf>,
+
+    /// with --repos, rebase this many repos at a time; defaults to 1
+    #[argh(option, default = "1")]
+    jobs: usize,
+
+    /// write a record of every git command that is run to this file, as JSON
+    /// lines; see `autorebase trace`
     #[argh(option)]
-    match_branches: Option<String>,
+    trace_file: Option<PathBuf>,
 
     /// RUST_LOG-style logging string, e.g. --log debug
     #[argh(option)]
     log: Option<String>,
+
+    #[argh(subcommand)]
+    command: Option<Subcommand>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Subcommand {
+    Log(LogCommand),
+    Resolve(ResolveCommand),
+    Status(StatusCommand),
+    Trace(TraceCommand),
+}
+
+#[derive(FromArgs)]
+/// Rebase a branch that is stuck due to conflicts onto the commit that
+/// conflicts, and start a shell so you can resolve them.
+#[argh(subcommand, name = "resolve")]
+struct ResolveCommand {
+    /// the branch to resolve
+    #[argh(positional)]
+    branch: String,
+
+    /// run `git mergetool` before starting the shell
+    #[argh(switch)]
+    mergetool: bool,
+
+    /// run this command to resolve the conflicts instead of starting an
+    /// interactive shell
+    #[argh(option)]
+    exec: Option<String>,
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            // Print a newline because there may be a half finished output
+            // (e.g. using `eprint!()` instead of `eprintln!()`.
+            eprintln!();
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn workspace_exit_code(outcome: &WorkspaceOutcome) -> u8 {
+    if outcome.has_errors() {
+        EXIT_ERROR
+    } else {
+        outcome
+            .outcomes()
+            .map(exit_code)
+            .filter(|&code| code != 0)
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+fn exit_code(outcome: &RunOutcome) -> u8 {
+    if outcome.has_failures() {
+        EXIT_BRANCH_ERRORS
+    } else if outcome.has_stuck() {
+        EXIT_BRANCHES_STUCK
+    } else if outcome.target_pull.not_pulled() {
+        EXIT_TARGET_NOT_PULLED
+    } else {
+        0
+    }
+}
+
+#[derive(FromArgs)]
+/// Show every branch, how far behind the target it is, and whether it would be
+/// rebased. This doesn't change anything.
+#[argh(subcommand, name = "status")]
+struct StatusCommand {
+    /// output JSON instead of a table
+    #[argh(switch)]
+    json: bool,
+}
+
+#[derive(FromArgs)]
+/// List previous runs and the branches they rebased, from the reflog.
+#[argh(subcommand, name = "log")]
+struct LogCommand {
+    /// only show the last N runs
+    #[argh(option, short = 'n')]
+    max_runs: Option<usize>,
+}
+
+fn parse_env_var(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .ok_or_else(|| format!("expected NAME=VALUE, got '{}'", value))
 }
 
-fn main() -> Result<()> {
-    let res = run();
-    if res.is_err() {
-        // Print a newline because there may be a half finished output
-        // (e.g. using `eprint!()` instead of `eprintln!()`.
-        eprintln!();
+/// Cancel running git commands on the first Ctrl-C so we can clean up, and
+/// exit immediately on the second. Ctrl-C is ignored while the user is
+/// running something interactively (e.g. in `autorebase resolve`) because it
+/// is meant for that.
+fn handle_ctrl_c(cancellation: CancellationToken) -> Result<()> {
+    ctrlc::set_handler(move || {
+        if is_interactive() {
+            return;
+        }
+        if cancellation.is_cancelled() {
+            std::process::exit(EXIT_ERROR.into());
+        }
+        eprintln!("\nCancelling... press Ctrl-C again to exit immediately");
+        cancellation.cancel();
+    })?;
+    Ok(())
+}
+
+#[derive(FromArgs)]
+/// List the git commands in a file written with --trace-file, or run them
+/// again.
+#[argh(subcommand, name = "trace")]
+struct TraceCommand {
+    /// the trace file
+    #[argh(positional)]
+    file: PathBuf,
+
+    /// only list commands that failed
+    #[argh(switch)]
+    failed: bool,
+
+    /// show everything about the command with this number
+    #[argh(option)]
+    show: Option<usize>,
+
+    /// run the commands again and list any whose exit code is different.
+    /// This changes the repo, so only use it on a copy
+    #[argh(switch)]
+    replay: bool,
+
+    /// when replaying, replace the path prefix OLD with NEW in working
+    /// directories and arguments, e.g. --map /home/them/repo=/tmp/repo; may
+    /// be given more than once
+    #[argh(option, from_str_fn(parse_path_map))]
+    map: Vec<(PathBuf, PathBuf)>,
+}
+
+fn parse_path_map(value: &str) -> Result<(PathBuf, PathBuf), String> {
+    value
+        .split_once('=')
+        .map(|(old, new)| (old.into(), new.into()))
+        .ok_or_else(|| format!("expected OLD=NEW, got '{}'", value))
+}
+
+fn trace(command: TraceCommand) -> Result<u8> {
+    let records = read_trace(&command.file)
+        .with_context(|| format!("Couldn't read trace file {}", command.file.display()))?;
+
+    if let Some(number) = command.show {
+        let record = number
+            .checked_sub(1)
+            .and_then(|index| records.get(index))
+            .ok_or_else(|| anyhow!("There is no command {} in the trace", number))?;
+        print_trace_record(record)?;
+    } else if command.replay {
+        let differences = replay_trace(&records, &command.map)?;
+        eprintln!(
+            "{} of {} commands behaved differently",
+            differences,
+            records.len()
+        );
+        if differences > 0 {
+            return Ok(EXIT_ERROR);
+        }
+    } else {
+        print_trace(&records, command.failed);
     }
-    res
+    Ok(0)
 }
 
-fn run() -> Result<()> {
+/// Run the command and return the exit code.
+fn run() -> Result<u8> {
     let options: CliOptions = argh::from_env();
 
     env_logger::Builder::new()
         .parse_filters(&options.log.unwrap_or_default())
         .init();
 
-    autorebase(
-        &current_dir()?,
-        options.onto.as_deref(),
-        options.slow,
-        options.include_non_local,
-        options.match_branches.as_deref(),
-    )?;
+    let cancellation = CancellationToken::new();
+    handle_ctrl_c(cancellation.clone())?;
 
-    Ok(())
+    git_commands::configure(Settings {
+        timeout: options.git_timeout.map(Duration::from_secs),
+        env: options.git_env,
+        cancellation: Some(cancellation),
+        trace: options
+            .trace_file
+            .as_deref()
+            .map(TraceFile::create)
+            .transpose()?,
+    });
+
+    let autorebase_options = AutorebaseOptions {
+        onto_branch: options.onto,
+        onto_commit: options.at,
+        quality_check: options.quality_check,
+        slow_conflict_detection: options.slow,
+        include_non_local: options.include_non_local,
+        branch_filter: BranchFilter::new(
+            &[options.match_, options.match_branches].concat(),
+            &options.exclude,
+            &options.match_regex,
+        )?,
+        only_mine: options.mine,
+        max_age: options
+            .max_age_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+        min_interval: options
+            .min_interval_hours
+            .map(|hours| Duration::from_secs(hours * 60 * 60)),
+        min_interval_commits: options.min_interval_commits,
+        wait_for_lock: options.wait,
+        signing_policy: options.sign,
+        autosquash: options.autosquash,
+        resolve_submodules: options.resolve_submodules,
+        recurse_submodules: options.recurse_submodules,
+        sparse_checkout: options.sparse,
+    };
+
+    if let Some(dir) = options.repos {
+        if options.command.is_some() {
+            bail!("--repos can't be used with subcommands");
+        }
+        let repos = find_repos(&dir)?;
+        if repos.is_empty() {
+            bail!("No git repos found in {}", dir.display());
+        }
+        let outcome = autorebase_repos(&repos, &autorebase_options, options.jobs);
+        outcome.print_summary(&dir);
+        return Ok(workspace_exit_code(&outcome));
+    }
+
+    match options.command {
+        None => {
+            let outcome = autorebase(&current_dir()?, &autorebase_options)?;
+            outcome.print_summary();
+            Ok(exit_code(&outcome))
+        }
+        Some(Subcommand::Resolve(command)) => {
+            resolve(
+                &current_dir()?,
+                &command.branch,
+                &autorebase_options,
+                &ResolveOptions {
+                    mergetool: command.mergetool,
+                    exec: command.exec,
+                },
+            )?;
+            Ok(0)
+        }
+        Some(Subcommand::Status(command)) => {
+            let status = status(&current_dir()?, &autorebase_options)?;
+            if command.json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                status.print();
+            }
+            Ok(0)
+        }
+        Some(Subcommand::Log(command)) => {
+            let mut runs = rebase_log(&current_dir()?)?;
+            if let Some(max_runs) = command.max_runs {
+                runs.truncate(max_runs);
+            }
+            print_rebase_log(&runs);
+            Ok(0)
+        }
+        Some(Subcommand::Trace(command)) => trace(command),
+    }
 }
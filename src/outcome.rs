@@ -0,0 +1,247 @@
+use colored::*;
+use serde::Serialize;
+use std::fmt;
+
+use crate::{print_repo_outcomes, RepoOutcome};
+
+// The result of an autorebase run, so that callers can tell what happened to
+// each branch without parsing the output.
+
+/// Why a branch was not considered for rebasing at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// It doesn't match the branch filters (`--match`, `--exclude` etc).
+    DoesNotMatchFilter,
+    /// It has an upstream and `--include-non-local` wasn't given.
+    HasUpstream,
+    /// It is checked out in a worktree with uncommitted changes.
+    CheckedOutNotClean,
+    /// Its tip commit wasn't authored by the current user and `--mine` was given.
+    NotMine,
+    /// Its tip commit was authored longer ago than `--max-age-days`.
+    TooOld,
+    /// `branch.<name>.autorebase` is false.
+    DisabledInConfig,
+}
+
+impl fmt::Display for SkipReason {
+    // Phrased to follow "skipping because it ...".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::DoesNotMatchFilter => "does not match branch filter",
+            Self::HasUpstream => "has an upstream",
+            Self::CheckedOutNotClean => "is checked out and not clean",
+            Self::NotMine => "was not authored by you",
+            Self::TooOld => "has not been worked on recently",
+            Self::DisabledInConfig => "has autorebase disabled in its git config",
+        })
+    }
+}
+
+/// What happened to a branch.
+#[derive(Debug)]
+pub enum BranchOutcome {
+    /// It was not considered for rebasing.
+    Skipped(SkipReason),
+    /// It was already on top of the target; nothing to do.
+    UpToDate,
+    /// It was rebased all the way onto the target.
+    Rebased,
+    /// It was rebased as far as possible but conflicts stopped it reaching
+    /// the target. It will be skipped until it is changed.
+    Conflicts,
+    /// It was skipped because it had conflicts last time and hasn't changed.
+    StillStuck,
+    /// It was skipped because we rebased it less than `--min-interval-hours`
+    /// ago and the target hasn't moved on much since.
+    RebasedRecently,
+    /// It was changed (e.g. committed to from another worktree) while we were
+    /// rebasing it, so it was left as it was. It will be rebased next time.
+    ChangedDuringRun,
+    /// It was skipped because its commits need to be signed but signing isn't
+    /// available.
+    SigningUnavailable,
+    /// An error occurred while rebasing it.
+    Failed(anyhow::Error),
+}
+
+impl BranchOutcome {
+    /// Is the branch stuck behind the target due to conflicts?
+    pub fn is_stuck(&self) -> bool {
+        matches!(self, Self::Conflicts | Self::StillStuck)
+    }
+
+    /// Did we fail to rebase the branch for reasons other than conflicts?
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Failed(_) | Self::SigningUnavailable)
+    }
+
+    /// Short description for the summary table.
+    fn summary(&self) -> ColoredString {
+        match self {
+            Self::Skipped(reason) => format!("skipped ({})", reason).dimmed(),
+            Self::UpToDate => "up to date".normal(),
+            Self::Rebased => "rebased".green(),
+            Self::Conflicts => "stuck (conflicts; rebase manually)".yellow(),
+            Self::StillStuck => "stuck (conflicts last time; rebase manually)".yellow(),
+            Self::RebasedRecently => "rebased recently (not rebased)".normal(),
+            Self::ChangedDuringRun => "changed during run (not rebased)".yellow(),
+            Self::SigningUnavailable => "failed (signing not available)".red(),
+            Self::Failed(_) => "failed (error)".red(),
+        }
+    }
+}
+
+/// The outcome for a single branch.
+#[derive(Debug)]
+pub struct BranchResult {
+    pub branch: String,
+    pub outcome: BranchOutcome,
+}
+
+/// Whether the target branch was pulled before rebasing onto it.
+#[derive(Debug)]
+pub enum TargetPull {
+    /// It was pulled.
+    Pulled,
+    /// It has no upstream so there was nothing to pull.
+    NoUpstream,
+    /// It has an upstream but it is checked out with pending changes, so it
+    /// couldn't be pulled.
+    NotClean,
+    /// Pulling it failed (e.g. we're offline), so branches were rebased onto
+    /// the local branch.
+    Failed(anyhow::Error),
+}
+
+impl TargetPull {
+    /// Does it have an upstream that it wasn't pulled from?
+    pub fn not_pulled(&self) -> bool {
+        matches!(self, Self::NotClean | Self::Failed(_))
+    }
+}
+
+/// The outcome of a whole autorebase run.
+#[derive(Debug)]
+pub struct RunOutcome {
+    /// The branch we rebased onto.
+    pub target_branch: String,
+    /// Whether `target_branch` was pulled.
+    pub target_pull: TargetPull,
+    /// Every branch apart from the target, in the order we processed them.
+    pub branches: Vec<BranchResult>,
+    /// The checked out submodules, if they were autorebased too.
+    pub submodules: Vec<RepoOutcome>,
+}
+
+impl RunOutcome {
+    /// The outcome for `branch`, if it isn't the target branch.
+    pub fn outcome(&self, branch: &str) -> Option<&BranchOutcome> {
+        self.branches
+            .iter()
+            .find(|b| b.branch == branch)
+            .map(|b| &b.outcome)
+    }
+
+    /// Did rebasing any branch fail, including in submodules?
+    pub fn has_failures(&self) -> bool {
+        self.branches.iter().any(|b| b.outcome.is_failure())
+            || self.submodules.iter().any(|s| match &s.outcome {
+                Ok(outcome) => outcome.has_failures(),
+                Err(_) => true,
+            })
+    }
+
+    /// Are any branches stuck due to conflicts, including in submodules?
+    pub fn has_stuck(&self) -> bool {
+        self.branches.iter().any(|b| b.outcome.is_stuck())
+            || self
+                .submodules
+                .iter()
+                .any(|s| s.outcome.as_ref().is_ok_and(|outcome| outcome.has_stuck()))
+    }
+
+    /// Print a table of the outcome for each branch.
+    pub fn print_summary(&self) {
+        eprintln!("• Summary:");
+
+        let width = self
+            .branches
+            .iter()
+            .map(|b| b.branch.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(self.target_branch.chars().count());
+
+        let target_status = match self.target_pull {
+            TargetPull::Pulled => "target (pulled)".normal(),
+            TargetPull::NoUpstream => "target (no upstream)".normal(),
+            TargetPull::NotClean => "target (not pulled; pending changes)".yellow(),
+            TargetPull::Failed(_) => "target (not pulled; error)".yellow(),
+        };
+        eprintln!(
+            "    {:width$}  {}",
+            self.target_branch.blue().bold(),
+            target_status,
+            width = width
+        );
+
+        for branch in &self.branches {
+            eprintln!(
+                "    {:width$}  {}",
+                branch.branch.bold(),
+                branch.outcome.summary(),
+                width = width
+            );
+        }
+
+        if !self.submodules.is_empty() {
+            eprintln!("• Submodules:");
+            print_repo_outcomes(&self.submodules, None);
+        }
+    }
+
+    /// A short description of how many branches had each outcome.
+    pub(crate) fn counts(&self) -> String {
+        let count =
+            |f: fn(&BranchOutcome) -> bool| self.branches.iter().filter(|b| f(&b.outcome)).count();
+        let counts = [
+            (
+                count(|o| matches!(o, BranchOutcome::Rebased)),
+                "rebased".green(),
+            ),
+            (
+                count(|o| matches!(o, BranchOutcome::UpToDate)),
+                "up to date".normal(),
+            ),
+            (
+                count(|o| matches!(o, BranchOutcome::RebasedRecently)),
+                "rebased recently".normal(),
+            ),
+            (count(BranchOutcome::is_stuck), "stuck".yellow()),
+            (
+                count(|o| matches!(o, BranchOutcome::ChangedDuringRun)),
+                "changed during run".yellow(),
+            ),
+            (count(BranchOutcome::is_failure), "failed".red()),
+            (
+                count(|o| matches!(o, BranchOutcome::Skipped(_))),
+                "skipped".dimmed(),
+            ),
+        ];
+
+        let mut parts: Vec<String> = counts
+            .iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, what)| format!("{} {}", n, what))
+            .collect();
+        if parts.is_empty() {
+            parts.push("no branches".to_owned());
+        }
+        if self.target_pull.not_pulled() {
+            parts.push("target not pulled".yellow().to_string());
+        }
+        parts.join(", ")
+    }
+}
@@ -0,0 +1,50 @@
+use std::{cell::RefCell, fmt};
+
+// Progress output for the user. This normally goes straight to stderr, but
+// when several repos are processed in parallel the output for each one is
+// collected and printed in one go so that they don't get mixed up.
+
+thread_local! {
+    static BUFFER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Like `eprint!()`, but collected by `buffered()`.
+macro_rules! report {
+    ($($arg:tt)*) => {
+        $crate::output::write_output(format_args!($($arg)*))
+    };
+}
+
+/// Like `eprintln!()`, but collected by `buffered()`.
+macro_rules! reportln {
+    () => {
+        report!("\n")
+    };
+    ($($arg:tt)*) => {
+        report!("{}\n", format_args!($($arg)*))
+    };
+}
+
+pub fn write_output(args: fmt::Arguments) {
+    BUFFER.with(|buffer| match buffer.borrow_mut().as_mut() {
+        Some(buffer) => {
+            // Writing to a `String` can't fail.
+            let _ = fmt::Write::write_fmt(buffer, args);
+        }
+        None => eprint!("{}", args),
+    });
+}
+
+/// Is output on this thread being collected by `buffered()`?
+pub fn is_buffered() -> bool {
+    BUFFER.with(|buffer| buffer.borrow().is_some())
+}
+
+/// Run `f`, collecting everything it reports on this thread instead of
+/// printing it.
+pub fn buffered<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let previous = BUFFER.with(|buffer| buffer.replace(Some(String::new())));
+    let result = f();
+    let output = BUFFER.with(|buffer| buffer.replace(previous));
+    (result, output.unwrap_or_default())
+}
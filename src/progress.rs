@@ -0,0 +1,54 @@
+use colored::*;
+use std::io::{stderr, IsTerminal};
+
+use crate::output::is_buffered;
+
+/// ANSI escape code to clear the rest of the line.
+const CLEAR_TO_END_OF_LINE: &str = "\x1b[K";
+
+/// Shows the progress of a long running git command after `prefix`, on a line
+/// that is cleared when this is dropped. Nothing is shown if stderr isn't a
+/// terminal because the output would just be noise in log files, or if the
+/// output is being buffered.
+pub struct ProgressLine {
+    prefix: String,
+    enabled: bool,
+    shown: bool,
+}
+
+impl ProgressLine {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            enabled: stderr().is_terminal() && !is_buffered(),
+            shown: false,
+        }
+    }
+
+    pub fn update(&mut self, progress: &str) {
+        if !self.enabled {
+            return;
+        }
+        eprint!(
+            "\r{}{}{}",
+            self.prefix,
+            progress.trim().dimmed(),
+            CLEAR_TO_END_OF_LINE
+        );
+        self.shown = true;
+    }
+}
+
+impl Drop for ProgressLine {
+    fn drop(&mut self) {
+        if self.shown {
+            eprint!("\r{}", CLEAR_TO_END_OF_LINE);
+        }
+    }
+}
+
+/// Convert git's `Rebasing (37/212)` messages to `applying 37/212`.
+pub fn rebase_progress(line: &str) -> Option<String> {
+    let counts = line.strip_prefix("Rebasing (")?.strip_suffix(')')?;
+    Some(format!("applying {}", counts))
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use git_commands::run_command;
+use log::debug;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+};
+
+use crate::resolve::shell_command;
+use crate::target::noted_commits;
+
+// Only rebasing onto commits on the target branch that are known to be good,
+// e.g. because they passed CI, so that one bad commit landing doesn't break
+// every branch.
+
+/// How to tell whether a commit on the target branch is good enough to rebase
+/// onto.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QualityCheck {
+    /// It has a note in this notes ref, e.g. `ci` for `refs/notes/ci`.
+    Notes(String),
+    /// This shell command succeeds. It is run in the repo with the commit
+    /// hash in `AUTOREBASE_COMMIT`.
+    Command(String),
+}
+
+impl FromStr for QualityCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("notes", notes_ref)) if !notes_ref.is_empty() => {
+                Ok(Self::Notes(notes_ref.to_owned()))
+            }
+            Some(("command", command)) if !command.is_empty() => {
+                Ok(Self::Command(command.to_owned()))
+            }
+            _ => Err(format!(
+                "invalid quality check '{}', expected notes:<ref> or command:<command>",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for QualityCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Notes(notes_ref) => write!(f, "notes:{}", notes_ref),
+            Self::Command(command) => write!(f, "command:{}", command),
+        }
+    }
+}
+
+/// Runs a `QualityCheck`, remembering the results since the same commits
+/// are checked for every branch.
+#[derive(Debug)]
+pub(crate) struct QualityChecker {
+    command: Option<String>,
+    working_dir: PathBuf,
+    results: RefCell<HashMap<String, bool>>,
+}
+
+impl QualityChecker {
+    /// Prepare to check commits in the repo containing `working_dir`.
+    pub(crate) fn new(check: &QualityCheck, working_dir: &Path) -> Result<Self> {
+        let mut checker = Self {
+            command: None,
+            working_dir: working_dir.to_owned(),
+            results: RefCell::default(),
+        };
+        match check {
+            // These are all known up front; anything else is bad.
+            QualityCheck::Notes(notes_ref) => {
+                let noted = noted_commits(working_dir, notes_ref)?;
+                checker
+                    .results
+                    .get_mut()
+                    .extend(noted.into_iter().map(|commit| (commit, true)));
+            }
+            QualityCheck::Command(command) => checker.command = Some(command.clone()),
+        }
+        Ok(checker)
+    }
+
+    fn is_good(&self, commit: &str) -> Result<bool> {
+        if let Some(&good) = self.results.borrow().get(commit) {
+            return Ok(good);
+        }
+        let Some(command) = &self.command else {
+            return Ok(false);
+        };
+        // This can take a while, so it gets the same timeout as git commands
+        // and is killed if we are cancelled.
+        let output = run_command(
+            shell_command(command)
+                .current_dir(&self.working_dir)
+                .env("AUTOREBASE_COMMIT", commit)
+                .stdin(Stdio::null()),
+        )
+        .with_context(|| format!("Couldn't run quality check '{}'", command))?;
+        debug!(
+            "Quality check for {}: {}\n{}{}",
+            commit,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let good = output.status.success();
+        self.results.borrow_mut().insert(commit.to_owned(), good);
+        Ok(good)
+    }
+
+    /// The index of the first good commit in `commits`, which are newest
+    /// first, or `None` if none of them are good.
+    pub(crate) fn newest_good(&self, commits: &[String]) -> Result<Option<usize>> {
+        for (index, commit) in commits.iter().enumerate() {
+            if self.is_good(commit)? {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+}
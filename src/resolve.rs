@@ -0,0 +1,255 @@
+use anyhow::{anyhow, bail, Result};
+use colored::*;
+use git_commands::{interactive, uncancellable};
+use std::{path::Path, process::Command};
+
+use crate::*;
+
+/// Options for `resolve()`.
+#[derive(Debug, Default)]
+pub struct ResolveOptions {
+    /// Run `git mergetool` before handing over to the user.
+    pub mergetool: bool,
+    /// Run this shell command to resolve the conflicts instead of starting
+    /// an interactive shell.
+    pub exec: Option<String>,
+}
+
+/// Help the user resolve the conflicts that stopped `branch` being rebased.
+///
+/// First the branch is rebased as far as possible, exactly as `autorebase()`
+/// would. Then we start rebasing it onto the next commit on the target branch,
+/// which is the one that conflicts, and start a shell in the worktree so the
+/// user can resolve the conflicts and `git rebase --continue`. If the rebase
+/// is finished when the shell exits the branch is no longer considered stuck.
+///
+pub fn resolve(
+    path: &Path,
+    branch_name: &str,
+    options: &AutorebaseOptions,
+    resolve_options: &ResolveOptions,
+) -> Result<()> {
+    let Session {
+        onto_branch,
+        worktree_root_path,
+        git_common_dir,
+        conflicts_path,
+        mut conflicts,
+        autorebase_worktree_path,
+        replay,
+        _lock,
+    } = Session::open(path, options)?;
+
+    if branch_name == onto_branch {
+        bail!("'{}' is the target branch", branch_name);
+    }
+
+    let all_branches = get_branches(&worktree_root_path)?;
+    let branch = all_branches
+        .iter()
+        .find(|b| b.branch == branch_name)
+        .ok_or_else(|| anyhow!("Couldn't find branch '{}'", branch_name))?;
+
+    if matches!(&branch.worktree, Some(worktree) if !worktree.clean) {
+        bail!(
+            "'{}' is checked out in '{}' and has pending changes",
+            branch_name,
+            branch.worktree.as_ref().unwrap().path.display()
+        );
+    }
+
+    // The user has asked to resolve this branch so try again even if it got
+    // stuck last time.
+    conflicts.branches.remove(branch_name);
+
+    let target_commit = resolve_target_commit(
+        &autorebase_worktree_path,
+        &onto_branch,
+        &options.onto_commit,
+    )?;
+
+    let quality_checker = options
+        .quality_check
+        .as_ref()
+        .map(|check| QualityChecker::new(check, &worktree_root_path))
+        .transpose()?;
+
+    let context = RebaseContext {
+        git_common_dir: &git_common_dir,
+        conflicts_path: &conflicts_path,
+        onto_branch: &onto_branch,
+        target_commit: &target_commit,
+        worktree_path: &autorebase_worktree_path,
+        options,
+        // We're interactive so the user can enter their passphrase if needed.
+        signing_available: true,
+        replay,
+        run_id: &new_run_id(),
+        quality_checker: quality_checker.as_ref(),
+    };
+
+    match rebase_branch(branch, &context, &mut conflicts)? {
+        BranchOutcome::Conflicts => {}
+        _ => {
+            eprintln!("{}", "• No conflicts to resolve".green());
+            return Ok(());
+        }
+    }
+
+    // `rebase_branch()` rebased as far as it could, so the first commit on
+    // the target after the merge base is the one that conflicts.
+    let (_, target_commit) = branch_target(
+        branch,
+        &onto_branch,
+        &target_commit,
+        &autorebase_worktree_path,
+    )?;
+    let merge_base = get_merge_base(&autorebase_worktree_path, branch_name, &target_commit)?
+        .ok_or_else(|| anyhow!("Branch has no common ancestor with the target"))?;
+    let target_commit_list =
+        get_commit_list(&autorebase_worktree_path, &merge_base, &target_commit)?;
+    let conflicting_commit = target_commit_list
+        .last()
+        .ok_or_else(|| anyhow!("Branch has conflicts but is already on the target"))?;
+
+    let sign =
+        options
+            .signing_policy
+            .should_sign(&autorebase_worktree_path, &merge_base, branch_name)?;
+
+    let rebase_worktree_path = if let Some(worktree) = &branch.worktree {
+        worktree.path.as_path()
+    } else {
+        switch_to_branch(branch_name, &autorebase_worktree_path)?;
+        autorebase_worktree_path.as_path()
+    };
+
+    eprintln!(
+        "• Rebasing {} onto conflicting commit {}",
+        branch_name.bold(),
+        conflicting_commit.bold()
+    );
+
+    let rebase_result = git_in_worktree(
+        &["rebase", rebase_signing_flag(sign), conflicting_commit],
+        rebase_worktree_path,
+    );
+
+    let worktree_name = get_worktree_name(rebase_worktree_path)?;
+
+    if rebase_result.is_err() {
+        if !is_rebasing(&git_common_dir, worktree_name.as_deref()) {
+            rebase_result?;
+        }
+
+        let user_result = interactive(|| hand_over_to_user(rebase_worktree_path, resolve_options));
+
+        if is_rebasing(&git_common_dir, worktree_name.as_deref()) {
+            // This has to happen even if we were cancelled before the shell
+            // started.
+            uncancellable(|| -> Result<()> {
+                git_in_worktree(&["rebase", "--abort"], rebase_worktree_path)?;
+                if branch.worktree.is_none() {
+                    git_in_worktree(
+                        &["switch", "--detach", branch_name],
+                        &autorebase_worktree_path,
+                    )?;
+                }
+                // Leave it marked as stuck.
+                let branch_commit = get_commit_hash(&autorebase_worktree_path, branch_name)?;
+                conflicts
+                    .branches
+                    .insert(branch_name.to_owned(), branch_commit);
+                conflicts.write_to_file(&conflicts_path)?;
+                Ok(())
+            })?;
+            user_result?;
+            bail!("The rebase was not finished so it has been aborted");
+        }
+
+        user_result?;
+    }
+
+    if branch.worktree.is_none() {
+        // Detach so we don't prevent people checking it out.
+        git_in_worktree(
+            &["switch", "--detach", branch_name],
+            &autorebase_worktree_path,
+        )?;
+    }
+
+    conflicts.branches.remove(branch_name);
+    conflicts.write_to_file(&conflicts_path)?;
+
+    eprintln!(
+        "{} {} {}",
+        "• Resolved".green(),
+        branch_name.green().bold(),
+        "- run autorebase again to rebase it the rest of the way".green()
+    );
+
+    Ok(())
+}
+
+/// Let the user resolve the conflicts in `worktree_path`, either with the
+/// command they gave or an interactive shell.
+fn hand_over_to_user(worktree_path: &Path, resolve_options: &ResolveOptions) -> Result<()> {
+    if resolve_options.mergetool {
+        run_interactive(Command::new("git").arg("mergetool"), worktree_path)?;
+    }
+
+    match &resolve_options.exec {
+        Some(command) => run_interactive(&mut shell_command(command), worktree_path),
+        None => {
+            eprintln!(
+                "{}\n    {}\n{}",
+                "• Starting a shell in".yellow(),
+                worktree_path.display().to_string().bold(),
+                "  Resolve the conflicts, run `git rebase --continue` and exit the shell \
+                 when done. Exit without finishing the rebase to abort it."
+                    .yellow(),
+            );
+            // The exit status of an interactive shell is just whatever the
+            // last command returned, so ignore it.
+            interactive_shell()
+                .current_dir(worktree_path)
+                .envs(worktree_env(worktree_path).iter().copied())
+                .status()?;
+            Ok(())
+        }
+    }
+}
+
+/// Run a command with the terminal attached, and fail if it fails.
+fn run_interactive(command: &mut Command, working_dir: &Path) -> Result<()> {
+    let status = command
+        .current_dir(working_dir)
+        .envs(worktree_env(working_dir).iter().copied())
+        .status()?;
+    if !status.success() {
+        bail!("{:?} failed: {}", command, status);
+    }
+    Ok(())
+}
+
+/// The user's shell.
+fn interactive_shell() -> Command {
+    if cfg!(windows) {
+        Command::new(std::env::var_os("COMSPEC").unwrap_or_else(|| "cmd.exe".into()))
+    } else {
+        Command::new(std::env::var_os("SHELL").unwrap_or_else(|| "sh".into()))
+    }
+}
+
+/// Run `command` with the system shell.
+pub(crate) fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.args(["/C", command]);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.args(["-c", command]);
+        c
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use git_commands::{git, git_with_env};
+use std::{path::Path, process::Output};
+
+use crate::trim::TrimAsciiWhitespace;
+
+// Our scratch worktree is only used for rebasing, so it doesn't need every
+// file. In big repos checking out LFS files, or directories excluded by
+// sparse-checkout, can make creating it and rebasing in it very slow.
+
+/// Where our scratch worktree is, relative to the main `.git` directory.
+pub(crate) const SCRATCH_WORKTREE_DIR: &str = "autorebase/autorebase_worktree";
+
+/// Extra environment for git commands run in `worktree_path`. In our scratch
+/// worktree Git LFS doesn't download files, and checks out the pointer files
+/// instead; rebasing only looks at those anyway. Other worktrees get the real
+/// files as usual.
+pub(crate) fn worktree_env(worktree_path: &Path) -> &'static [(&'static str, &'static str)] {
+    if worktree_path.ends_with(SCRATCH_WORKTREE_DIR) {
+        &[("GIT_LFS_SKIP_SMUDGE", "1")]
+    } else {
+        &[]
+    }
+}
+
+/// Run a git command in `worktree_path` with `worktree_env()`. Use this for
+/// commands that may check files out.
+pub(crate) fn git_in_worktree(
+    args: &[&str],
+    worktree_path: &Path,
+) -> Result<Output, git_commands::Error> {
+    git_with_env(args, worktree_path, worktree_env(worktree_path))
+}
+
+/// Create our scratch worktree at `worktree_path` for the repo containing
+/// `working_dir`. See `prepare_scratch_worktree()` for the other arguments.
+pub(crate) fn create_scratch_worktree(
+    working_dir: &Path,
+    worktree_path: &Path,
+    sparse_dirs: &[String],
+    inherit_sparse_from: Option<&Path>,
+) -> Result<()> {
+    let worktree_path_str = worktree_path
+        .to_str()
+        .ok_or_else(|| anyhow!("worktree path is not unicode"))?;
+    // Don't check anything out until it is set up.
+    git(
+        &[
+            "worktree",
+            "add",
+            "--no-checkout",
+            "--detach",
+            worktree_path_str,
+        ],
+        working_dir,
+    )?;
+    prepare_scratch_worktree(worktree_path, sparse_dirs, inherit_sparse_from)?;
+    git_in_worktree(&["checkout", "--detach"], worktree_path)?;
+    Ok(())
+}
+
+/// Apply our settings to the scratch worktree. This is done every run so
+/// that changes to them are picked up.
+///
+/// Only `sparse_dirs` are checked out (using cone mode) if any are given.
+/// Otherwise it gets the same sparse-checkout settings as the worktree
+/// `inherit_sparse_from`, if that is given. It shouldn't be before Git 2.36,
+/// where `git sparse-checkout` changes the main worktree too.
+pub(crate) fn prepare_scratch_worktree(
+    worktree_path: &Path,
+    sparse_dirs: &[String],
+    inherit_sparse_from: Option<&Path>,
+) -> Result<()> {
+    let wanted = if !sparse_dirs.is_empty() {
+        Some(SparseCheckout {
+            cone: true,
+            patterns: sparse_dirs.to_vec(),
+        })
+    } else if let Some(source) = inherit_sparse_from {
+        sparse_checkout(source)?
+    } else {
+        return Ok(());
+    };
+    if sparse_checkout(worktree_path)? == wanted {
+        return Ok(());
+    }
+    match wanted {
+        Some(sparse) => {
+            let mut args = vec![
+                "sparse-checkout",
+                "set",
+                if sparse.cone { "--cone" } else { "--no-cone" },
+            ];
+            args.extend(sparse.patterns.iter().map(String::as_str));
+            git_in_worktree(&args, worktree_path)?;
+        }
+        None => {
+            git_in_worktree(&["sparse-checkout", "disable"], worktree_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// A worktree's sparse-checkout settings.
+#[derive(Debug, PartialEq, Eq)]
+struct SparseCheckout {
+    /// Whether cone mode is used.
+    cone: bool,
+    /// The directories to check out in cone mode, or the patterns otherwise.
+    patterns: Vec<String>,
+}
+
+/// Get the sparse-checkout settings of the worktree at `worktree_path`, or
+/// `None` if everything is checked out.
+fn sparse_checkout(worktree_path: &Path) -> Result<Option<SparseCheckout>> {
+    if config_value_with(&["--type=bool"], "core.sparseCheckout", worktree_path)? != "true" {
+        return Ok(None);
+    }
+    let cone =
+        config_value_with(&["--type=bool"], "core.sparseCheckoutCone", worktree_path)? == "true";
+    let output = git(&["sparse-checkout", "list"], worktree_path)?.stdout;
+    let patterns = String::from_utf8(output)?
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    Ok(Some(SparseCheckout { cone, patterns }))
+}
+
+/// Get a git config setting, or an empty string if it isn't set. `options`
+/// are extra options for `git config`, e.g. to say what type the value is.
+fn config_value_with(options: &[&str], key: &str, working_dir: &Path) -> Result<String> {
+    let mut args = vec!["config"];
+    args.extend(options);
+    args.extend(["--default", "", "--get", key]);
+    let output = git(&args, working_dir)?.stdout;
+    Ok(std::str::from_utf8(output.trim_ascii_whitespace())?.to_owned())
+}
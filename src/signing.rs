@@ -0,0 +1,139 @@
+use anyhow::{bail, Result};
+use git_commands::git;
+use std::{fmt, path::Path, str::FromStr};
+
+use crate::trim::TrimAsciiWhitespace;
+
+/// Whether rebased commits should be signed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SigningPolicy {
+    /// Follow the `commit.gpgsign` git config setting, like a normal rebase.
+    #[default]
+    Config,
+    /// Always sign rebased commits.
+    Always,
+    /// Never sign rebased commits.
+    Never,
+    /// Sign rebased commits only if the branch already contains signed commits.
+    PreserveIfSigned,
+}
+
+impl FromStr for SigningPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "config" => Ok(Self::Config),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            "preserve-if-signed" => Ok(Self::PreserveIfSigned),
+            _ => Err(format!(
+                "invalid signing policy '{}', expected one of: config, always, never, preserve-if-signed",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SigningPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Config => "config",
+            Self::Always => "always",
+            Self::Never => "never",
+            Self::PreserveIfSigned => "preserve-if-signed",
+        })
+    }
+}
+
+impl SigningPolicy {
+    /// Could this policy cause any commits to be signed in this repo? Used to
+    /// avoid checking whether signing works when it will never be needed.
+    pub fn may_sign(self, working_dir: &Path) -> Result<bool> {
+        Ok(match self {
+            Self::Config => config_gpgsign(working_dir)?,
+            Self::Always | Self::PreserveIfSigned => true,
+            Self::Never => false,
+        })
+    }
+
+    /// Should the commits from `merge_base` to `branch` be signed when they
+    /// are rebased?
+    pub fn should_sign(self, working_dir: &Path, merge_base: &str, branch: &str) -> Result<bool> {
+        Ok(match self {
+            Self::Config => config_gpgsign(working_dir)?,
+            Self::Always => true,
+            Self::Never => false,
+            Self::PreserveIfSigned => has_signed_commits(working_dir, merge_base, branch)?,
+        })
+    }
+}
+
+/// The `git rebase` flag to explicitly enable or disable signing.
+pub fn rebase_signing_flag(sign: bool) -> &'static str {
+    if sign {
+        "--gpg-sign"
+    } else {
+        "--no-gpg-sign"
+    }
+}
+
+/// Read the `commit.gpgsign` git config setting.
+fn config_gpgsign(working_dir: &Path) -> Result<bool> {
+    let output = git(
+        &[
+            "--no-pager",
+            "config",
+            "--type=bool",
+            "--default",
+            "false",
+            "--get",
+            "commit.gpgsign",
+        ],
+        working_dir,
+    )?
+    .stdout;
+    match output.trim_ascii_whitespace() {
+        b"true" => Ok(true),
+        b"false" => Ok(false),
+        other => bail!(
+            "Unexpected commit.gpgsign value: '{}'",
+            String::from_utf8_lossy(other)
+        ),
+    }
+}
+
+/// Do any of the commits from `from` to `to` have a signature? It doesn't matter
+/// whether we can verify it.
+fn has_signed_commits(working_dir: &Path, from: &str, to: &str) -> Result<bool> {
+    let output = git(
+        &[
+            "--no-pager",
+            "log",
+            "--format=%G?",
+            &format!("{}..{}", from, to),
+        ],
+        working_dir,
+    )?
+    .stdout;
+    let output = std::str::from_utf8(&output)?;
+    // `N` means no signature. Everything else is some kind of signature.
+    Ok(output.lines().any(|status| status.trim() != "N"))
+}
+
+/// Check that we can sign commits without user interaction by making a
+/// throwaway signed commit object with the same tree as `commit`. It is not
+/// referenced by anything so it will be garbage collected eventually.
+pub fn signing_available(working_dir: &Path, commit: &str) -> bool {
+    git(
+        &[
+            "commit-tree",
+            "--gpg-sign",
+            "-m",
+            "autorebase signing check",
+            &format!("{}^{{tree}}", commit),
+        ],
+        working_dir,
+    )
+    .is_ok()
+}
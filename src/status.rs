@@ -0,0 +1,234 @@
+use anyhow::{anyhow, Result};
+use colored::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::*;
+
+/// Autorebase's view of a single branch.
+#[derive(Debug, Serialize)]
+pub struct BranchStatus {
+    pub branch: String,
+    /// The upstream branch, if any.
+    pub upstream: Option<String>,
+    /// The worktree it is checked out in, if any.
+    pub worktree: Option<PathBuf>,
+    /// The branch it would be rebased onto instead of the target branch, from
+    /// `branch.<name>.autorebaseOnto`.
+    pub onto: Option<String>,
+    /// Its `branch.<name>.description`.
+    pub description: Option<String>,
+    /// Number of commits on the target (or `onto`) that aren't on this
+    /// branch, not counting any after the newest one that passes the quality
+    /// check. `None` if the branches have no common ancestor.
+    pub behind: Option<usize>,
+    /// Number of commits on this branch that aren't on the target. `None` if
+    /// the branches have no common ancestor.
+    pub ahead: Option<usize>,
+    /// True if it got stuck due to conflicts last time and hasn't changed
+    /// since, so autorebase won't try it again.
+    pub stuck: bool,
+    /// True if autorebase rebased it too recently to do it again (see
+    /// `AutorebaseOptions::min_interval`).
+    pub rebased_recently: bool,
+    /// If autorebase would skip it, why.
+    pub skip_reason: Option<SkipReason>,
+    /// If one of its `branch.<name>.*` settings is invalid, or its target
+    /// couldn't be found (e.g. because `branch.<name>.autorebaseOnto` names a
+    /// branch that doesn't exist), the error. Autorebase would fail to rebase
+    /// it.
+    pub error: Option<String>,
+}
+
+/// Autorebase's view of a repo.
+#[derive(Debug, Serialize)]
+pub struct RepoStatus {
+    /// The branch that the others would be rebased onto.
+    pub target_branch: String,
+    /// The commit that they would be rebased onto. This is normally the tip
+    /// of `target_branch`, which isn't pulled first.
+    pub target_commit: String,
+    /// All other branches.
+    pub branches: Vec<BranchStatus>,
+}
+
+/// Get the status of all branches in the repo containing `path`, as they
+/// would be seen by `autorebase()` with the same options. Unlike
+/// `autorebase()` this doesn't change anything, so it doesn't take the lock
+/// or pull the target branch.
+pub fn status(path: &Path, options: &AutorebaseOptions) -> Result<RepoStatus> {
+    let onto_branch = target_branch_name(path, options)?;
+    let worktree_root_path = get_worktree_path(path)?;
+    let git_common_dir = get_git_common_dir(&worktree_root_path)?;
+
+    let conflicts_path = git_common_dir.join("autorebase/conflicts.toml");
+    let conflicts = if conflicts_path.is_file() {
+        Conflicts::read_from_file(&conflicts_path)?
+    } else {
+        Default::default()
+    };
+
+    let current_user_email = current_user_email_if_needed(&worktree_root_path, options)?;
+
+    let all_branches = get_branches(&worktree_root_path)?;
+    if !all_branches.iter().any(|b| b.branch == onto_branch) {
+        return Err(anyhow!("Couldn't find target branch '{}'", onto_branch));
+    }
+
+    let target_commit =
+        resolve_target_commit(&worktree_root_path, &onto_branch, &options.onto_commit)?;
+
+    let quality_checker = options
+        .quality_check
+        .as_ref()
+        .map(|check| QualityChecker::new(check, &worktree_root_path))
+        .transpose()?;
+
+    let mut branches = Vec::with_capacity(all_branches.len());
+
+    for branch in all_branches.iter() {
+        if branch.branch == onto_branch {
+            continue;
+        }
+
+        // Bad `branch.<name>.*` settings only affect that branch.
+        let target = match &branch.config.error {
+            Some(error) => Err(anyhow!("{}", error)),
+            None => branch_target(branch, &onto_branch, &target_commit, &worktree_root_path),
+        };
+        let (counts, error) = match target {
+            Ok((branch_onto, branch_target_commit)) => {
+                // As when rebasing, the quality check is only for the
+                // target branch.
+                let quality_checker = quality_checker
+                    .as_ref()
+                    .filter(|_| branch_onto == onto_branch);
+                (
+                    behind_ahead(
+                        &worktree_root_path,
+                        &branch.branch,
+                        &branch_target_commit,
+                        quality_checker,
+                    )?,
+                    None,
+                )
+            }
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        let (behind, ahead) = counts.unzip();
+
+        let branch_commit = get_commit_hash(&worktree_root_path, &branch.branch)?;
+        let stuck = conflicts.branches.get(&branch.branch) == Some(&branch_commit);
+        let rebased_recently = behind.is_some_and(|behind| {
+            behind > 0 && rebased_recently(&branch.branch, behind, &conflicts, options)
+        });
+
+        branches.push(BranchStatus {
+            branch: branch.branch.clone(),
+            upstream: branch.upstream.clone(),
+            worktree: branch.worktree.as_ref().map(|w| w.path.clone()),
+            onto: branch
+                .config
+                .onto
+                .clone()
+                .filter(|onto| *onto != onto_branch),
+            description: branch.config.description.clone(),
+            behind,
+            ahead,
+            stuck,
+            rebased_recently,
+            skip_reason: skip_reason(branch, options, current_user_email.as_deref()),
+            error,
+        });
+    }
+
+    Ok(RepoStatus {
+        target_branch: onto_branch,
+        target_commit,
+        branches,
+    })
+}
+
+/// How many commits `branch` is behind and ahead of `target_commit`, or
+/// `None` if they have no common ancestor. Only commits up to the newest one
+/// that passes `quality_checker` count as behind, since it wouldn't be rebased
+/// past that.
+fn behind_ahead(
+    working_dir: &Path,
+    branch: &str,
+    target_commit: &str,
+    quality_checker: Option<&QualityChecker>,
+) -> Result<Option<(usize, usize)>> {
+    let Some(merge_base) = get_merge_base(working_dir, branch, target_commit)? else {
+        return Ok(None);
+    };
+    let mut target_commit_list = get_commit_list(working_dir, &merge_base, target_commit)?;
+    if let Some(checker) = quality_checker {
+        let good = checker
+            .newest_good(&target_commit_list)?
+            .unwrap_or(target_commit_list.len());
+        target_commit_list.drain(..good);
+    }
+    Ok(Some((
+        target_commit_list.len(),
+        get_commit_list(working_dir, &merge_base, branch)?.len(),
+    )))
+}
+
+impl RepoStatus {
+    /// Print a human readable table to stdout.
+    pub fn print(&self) {
+        println!(
+            "Target branch: {} at {}",
+            self.target_branch.blue().bold(),
+            self.target_commit
+        );
+
+        let width = self
+            .branches
+            .iter()
+            .map(|b| b.branch.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        for branch in &self.branches {
+            let counts = match (branch.behind, branch.ahead) {
+                (Some(behind), Some(ahead)) => format!("{} behind, {} ahead", behind, ahead),
+                _ if branch.error.is_some() => "unknown".to_owned(),
+                _ => "no common ancestor".to_owned(),
+            };
+
+            let state = if let Some(error) = &branch.error {
+                format!("error: {}", error).red()
+            } else if let Some(reason) = branch.skip_reason {
+                format!("skipped because it {}", reason).dimmed()
+            } else if branch.stuck {
+                "stuck due to conflicts; rebase manually".yellow()
+            } else if branch.rebased_recently {
+                "rebased recently; will be left alone".dimmed()
+            } else if branch.behind == Some(0) {
+                "up to date".green()
+            } else {
+                "will be rebased".normal()
+            };
+
+            let counts = match &branch.onto {
+                Some(onto) => format!("{} (onto {})", counts, onto.blue()),
+                None => counts,
+            };
+
+            println!(
+                "    {:width$}  {}, {}",
+                branch.branch.bold(),
+                counts,
+                state,
+                width = width
+            );
+            if let Some(description) = &branch.description {
+                for line in description.lines() {
+                    println!("    {:width$}  {}", "", line.dimmed(), width = width);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::Result;
+use colored::*;
+use git_commands::{git, git_with_env, Error};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::*;
+
+// Submodules aren't checked out in our scratch worktree, so when a branch and
+// the target both change a submodule's commit git can't tell whether one
+// includes the other and reports a conflict.
+
+/// Index mode of submodule entries ("gitlinks").
+const GITLINK_MODE: &str = "160000";
+
+/// Get the name and path of each submodule in `.gitmodules` in `worktree_path`.
+fn submodules(worktree_path: &Path) -> Result<Vec<(String, String)>> {
+    if !worktree_path.join(".gitmodules").is_file() {
+        return Ok(Vec::new());
+    }
+    let output = match git(
+        &[
+            "config",
+            "--file",
+            ".gitmodules",
+            "--null",
+            "--get-regexp",
+            r"^submodule\..*\.path$",
+        ],
+        worktree_path,
+    ) {
+        Ok(output) => output.stdout,
+        // No matches.
+        Err(Error::Process(e)) if e.output().status.code() == Some(1) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let output = String::from_utf8(output)?;
+    // Each entry is `submodule.<name>.path\n<path>\0`.
+    Ok(output
+        .split_terminator('\0')
+        .filter_map(|entry| {
+            let (key, path) = entry.split_once('\n')?;
+            let name = key.strip_prefix("submodule.")?.strip_suffix(".path")?;
+            Some((name.to_owned(), path.to_owned()))
+        })
+        .collect())
+}
+
+/// Get the paths of the submodules that are checked out in `worktree_path`.
+pub(crate) fn checked_out_submodules(worktree_path: &Path) -> Result<Vec<PathBuf>> {
+    Ok(submodules(worktree_path)?
+        .into_iter()
+        .map(|(_, path)| worktree_path.join(path))
+        .filter(|path| path.join(".git").exists())
+        .collect())
+}
+
+/// Get the git directory of the submodule at `path`, if it has been cloned.
+fn submodule_git_dir(
+    git_common_dir: &Path,
+    worktree_path: &Path,
+    path: &str,
+) -> Result<Option<PathBuf>> {
+    let name = submodules(worktree_path)?
+        .into_iter()
+        .find(|(_, p)| p == path)
+        .map_or_else(|| path.to_owned(), |(name, _)| name);
+    let git_dir = git_common_dir.join("modules").join(name);
+    Ok(git_dir.is_dir().then_some(git_dir))
+}
+
+/// A conflict between two commits of a submodule.
+#[derive(Debug)]
+struct GitlinkConflict {
+    path: String,
+    /// The commit on the branch being rebased onto. `None` if it was deleted.
+    ours: Option<String>,
+    /// The commit being applied. `None` if it was deleted.
+    theirs: Option<String>,
+}
+
+/// The paths that have conflicts in the index of `worktree_path`.
+#[derive(Debug, Default)]
+struct UnmergedPaths {
+    gitlinks: Vec<GitlinkConflict>,
+    /// Anything that isn't a submodule.
+    others: Vec<String>,
+}
+
+fn unmerged_paths(worktree_path: &Path) -> Result<UnmergedPaths> {
+    let output = git(&["ls-files", "--unmerged", "-z"], worktree_path)?.stdout;
+    let output = String::from_utf8(output)?;
+
+    // Each entry is `<mode> <object> <stage>\t<path>\0`, with one entry for
+    // each stage: 1 is the base, 2 is ours and 3 is theirs.
+    let mut stages: BTreeMap<&str, Vec<(&str, &str, &str)>> = BTreeMap::new();
+    for entry in output.split_terminator('\0') {
+        let Some((info, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let mut info = info.split(' ');
+        if let (Some(mode), Some(object), Some(stage)) = (info.next(), info.next(), info.next()) {
+            stages.entry(path).or_default().push((mode, object, stage));
+        }
+    }
+
+    let mut unmerged = UnmergedPaths::default();
+    for (path, entries) in stages {
+        if entries.iter().any(|(mode, _, _)| *mode == GITLINK_MODE) {
+            let side = |stage: &str| {
+                entries
+                    .iter()
+                    .find(|(mode, _, s)| *mode == GITLINK_MODE && *s == stage)
+                    .map(|(_, object, _)| object.to_string())
+            };
+            unmerged.gitlinks.push(GitlinkConflict {
+                path: path.to_owned(),
+                ours: side("2"),
+                theirs: side("3"),
+            });
+        } else {
+            unmerged.others.push(path.to_owned());
+        }
+    }
+    Ok(unmerged)
+}
+
+/// Resolve conflicts between submodule commits by taking the newer one, if
+/// one is a descendant of the other. Returns false if any couldn't be
+/// resolved.
+fn resolve_gitlink_conflicts(
+    git_common_dir: &Path,
+    worktree_path: &Path,
+    conflicts: &[GitlinkConflict],
+) -> Result<bool> {
+    for conflict in conflicts {
+        let (Some(ours), Some(theirs)) = (&conflict.ours, &conflict.theirs) else {
+            return Ok(false);
+        };
+        let Some(submodule_git_dir) =
+            submodule_git_dir(git_common_dir, worktree_path, &conflict.path)?
+        else {
+            return Ok(false);
+        };
+        // This also fails if either commit hasn't been fetched.
+        let is_ancestor = |a: &str, b: &str| {
+            git(&["merge-base", "--is-ancestor", a, b], &submodule_git_dir).is_ok()
+        };
+        let newer = if is_ancestor(ours, theirs) {
+            theirs
+        } else if is_ancestor(theirs, ours) {
+            ours
+        } else {
+            return Ok(false);
+        };
+        git(
+            &[
+                "update-index",
+                "--cacheinfo",
+                &format!("{},{},{}", GITLINK_MODE, newer, conflict.path),
+            ],
+            worktree_path,
+        )?;
+    }
+    Ok(true)
+}
+
+/// Called when a rebase in `worktree_path` failed with `error`. If `resolve`
+/// is true, resolve conflicts between submodule commits and continue the
+/// rebase for as long as those are the only conflicts. `env` is passed to
+/// `git rebase --continue`, e.g. to keep the same `GIT_REFLOG_ACTION`.
+///
+/// Returns `None` if the rebase was finished, otherwise the error that
+/// stopped it, in which case the rebase may still be in progress.
+pub(crate) fn continue_past_submodule_conflicts(
+    git_common_dir: &Path,
+    worktree_path: &Path,
+    mut error: Error,
+    resolve: bool,
+    env: &[(&str, &str)],
+) -> Result<Option<Error>> {
+    if !resolve {
+        return Ok(Some(error));
+    }
+    loop {
+        let worktree = get_worktree_name(worktree_path)?;
+        if !is_rebasing(git_common_dir, worktree.as_deref()) || !is_conflict(&error, true) {
+            return Ok(Some(error));
+        }
+
+        let unmerged = unmerged_paths(worktree_path)?;
+        if unmerged.gitlinks.is_empty()
+            || !unmerged.others.is_empty()
+            || !resolve_gitlink_conflicts(git_common_dir, worktree_path, &unmerged.gitlinks)?
+        {
+            return Ok(Some(error));
+        }
+
+        match git_with_env(&["rebase", "--continue"], worktree_path, env) {
+            Ok(_) => return Ok(None),
+            Err(e) => error = e,
+        }
+    }
+}
+
+/// If a rebase in `worktree_path` has stopped because of conflicting
+/// submodule commits, say so, since they are easy to miss.
+pub(crate) fn report_submodule_conflicts(worktree_path: &Path, resolve: bool) -> Result<()> {
+    let unmerged = unmerged_paths(worktree_path)?;
+    for conflict in &unmerged.gitlinks {
+        reportln!(
+            "    - Conflicting commits for submodule {}{}",
+            conflict.path.bold(),
+            if resolve {
+                ""
+            } else {
+                " (--resolve-submodules may be able to fix this)"
+            }
+        );
+    }
+    Ok(())
+}
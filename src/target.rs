@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Context, Result};
+use git_commands::git;
+use std::{collections::HashSet, fmt, path::Path, str::FromStr};
+
+use crate::*;
+
+// By default branches are rebased onto the tip of the target branch, but that
+// may have just been broken. These let an older commit be chosen instead,
+// such as the last one that passed CI.
+
+/// Which commit to rebase onto.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum TargetCommit {
+    /// The tip of the target branch.
+    #[default]
+    Tip,
+    /// A tag, commit hash or any other revision git understands.
+    Revision(String),
+    /// The newest commit on the target branch (following first parents) that
+    /// has a note in this notes ref, e.g. `ci` for `refs/notes/ci`.
+    Noted(String),
+    /// The newest commit on the target branch (following first parents) that
+    /// was committed before this date, e.g. `yesterday 09:00`.
+    Before(String),
+}
+
+impl FromStr for TargetCommit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let target = if s == "tip" {
+            Self::Tip
+        } else if let Some(notes_ref) = s.strip_prefix("notes:") {
+            Self::Noted(notes_ref.to_owned())
+        } else if let Some(date) = s.strip_prefix("before:") {
+            Self::Before(date.to_owned())
+        } else {
+            Self::Revision(s.to_owned())
+        };
+        match &target {
+            Self::Revision(s) | Self::Noted(s) | Self::Before(s) if s.is_empty() => {
+                Err("expected tip, a revision, notes:<ref> or before:<date>".to_owned())
+            }
+            _ => Ok(target),
+        }
+    }
+}
+
+impl fmt::Display for TargetCommit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tip => f.write_str("tip"),
+            Self::Revision(revision) => f.write_str(revision),
+            Self::Noted(notes_ref) => write!(f, "notes:{}", notes_ref),
+            Self::Before(date) => write!(f, "before:{}", date),
+        }
+    }
+}
+
+/// Get the hash of the commit to rebase onto, given the `target` on
+/// `onto_branch`.
+pub(crate) fn resolve_target_commit(
+    working_dir: &Path,
+    onto_branch: &str,
+    target: &TargetCommit,
+) -> Result<String> {
+    match target {
+        TargetCommit::Tip => get_commit_hash(working_dir, onto_branch),
+        // Peel tags to the commit they point to.
+        TargetCommit::Revision(revision) => {
+            get_commit_hash(working_dir, &format!("{}^{{commit}}", revision))
+                .with_context(|| format!("Couldn't find target commit '{}'", revision))
+        }
+        TargetCommit::Noted(notes_ref) => {
+            let noted = noted_commits(working_dir, notes_ref)?;
+            first_parent_history(working_dir, onto_branch, &[])?
+                .into_iter()
+                .find(|commit| noted.contains(commit))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No commit on '{}' has a note in '{}'",
+                        onto_branch,
+                        notes_ref
+                    )
+                })
+        }
+        TargetCommit::Before(date) => {
+            let before = format!("--before={}", date);
+            first_parent_history(working_dir, onto_branch, &["-1", &before])?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No commit on '{}' was committed before '{}'",
+                        onto_branch,
+                        date
+                    )
+                })
+        }
+    }
+}
+
+/// The commits that have a note in `notes_ref`. Like `git notes --ref`, `ci`
+/// means `refs/notes/ci`.
+pub(crate) fn noted_commits(working_dir: &Path, notes_ref: &str) -> Result<HashSet<String>> {
+    let output = git(&["notes", "--ref", notes_ref, "list"], working_dir)?.stdout;
+    let output = String::from_utf8(output)?;
+    // Each line is `<note object> <annotated object>`.
+    Ok(output
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(_, commit)| commit.to_owned())
+        .collect())
+}
+
+/// The commits on `branch`, newest first, following first parents so that
+/// we only get commits that were actually at the tip at some point (assuming
+/// people merge into it rather than the other way round).
+fn first_parent_history(working_dir: &Path, branch: &str, args: &[&str]) -> Result<Vec<String>> {
+    let mut rev_list_args = vec!["rev-list", "--first-parent"];
+    rev_list_args.extend(args);
+    rev_list_args.extend([branch, "--"]);
+    let output = git(&rev_list_args, working_dir)?.stdout;
+    let output = String::from_utf8(output)?;
+    Ok(output.lines().map(str::to_owned).collect())
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use colored::*;
+use git_commands::{git_internal, Error, TraceRecord, REDACTED};
+use std::path::{Path, PathBuf};
+
+// Inspecting and replaying traces written with `--trace-file`, to help
+// reproduce bugs.
+
+/// Print a one line summary of each command in a trace, numbered from 1.
+pub fn print_trace(records: &[TraceRecord], failed_only: bool) {
+    for (index, record) in records.iter().enumerate() {
+        if failed_only && record.succeeded() {
+            continue;
+        }
+
+        let status = match (&record.error, record.exit_code) {
+            (Some(_), _) => "error".red(),
+            (None, Some(0)) => "ok".green(),
+            (None, Some(code)) => format!("exit {}", code).red(),
+            (None, None) => "killed".red(),
+        };
+
+        println!(
+            "{:>4} {:>7} {:7} {} $ git {}",
+            index + 1,
+            format!("{}ms", record.duration_ms),
+            status,
+            record
+                .cwd
+                .as_deref()
+                .unwrap_or(Path::new("."))
+                .display()
+                .to_string()
+                .dimmed(),
+            record.args.join(" ").bold()
+        );
+    }
+}
+
+/// Print everything about one command.
+pub fn print_trace_record(record: &TraceRecord) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(record)?);
+    Ok(())
+}
+
+/// Replace the prefix `old` of `path` with `new` for the first `(old, new)`
+/// pair that matches.
+fn map_path(path: &str, path_map: &[(PathBuf, PathBuf)]) -> String {
+    for (old, new) in path_map {
+        if let Ok(rest) = Path::new(path).strip_prefix(old) {
+            let path = if rest.as_os_str().is_empty() {
+                new.clone()
+            } else {
+                new.join(rest)
+            };
+            return path.to_string_lossy().into_owned();
+        }
+    }
+    path.to_owned()
+}
+
+/// Run the commands in a trace again, in order, and print any whose exit code
+/// is different from the recorded one. Working directories and arguments
+/// that start with one of the old paths in `path_map` are changed to start
+/// with the new path instead, so that it can be run on a copy of the repo.
+///
+/// Returns the number of commands that behaved differently.
+pub fn replay_trace(records: &[TraceRecord], path_map: &[(PathBuf, PathBuf)]) -> Result<usize> {
+    let mut differences = 0;
+
+    for (index, record) in records.iter().enumerate() {
+        let cwd = record
+            .cwd
+            .as_ref()
+            .map(|cwd| PathBuf::from(map_path(&cwd.to_string_lossy(), path_map)));
+        let args: Vec<String> = record
+            .args
+            .iter()
+            .map(|arg| map_path(arg, path_map))
+            .collect();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        // Redacted values come from `--git-env` instead, if it is given again.
+        let env: Vec<(&str, &str)> = record
+            .env
+            .iter()
+            .filter(|(_, v)| v != REDACTED)
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+
+        let exit_code = match git_internal(&args, cwd.as_deref(), &env) {
+            Ok(_) => Some(0),
+            Err(Error::Process(e)) => e.output().status.code(),
+            Err(_) => None,
+        };
+
+        if exit_code != record.exit_code {
+            differences += 1;
+            println!(
+                "{:>4} {} $ git {}: recorded {:?}, got {:?}",
+                index + 1,
+                cwd.as_deref()
+                    .unwrap_or(Path::new("."))
+                    .display()
+                    .to_string()
+                    .dimmed(),
+                args.join(" ").bold(),
+                record.exit_code,
+                exit_code,
+            );
+        }
+    }
+
+    Ok(differences)
+}
@@ -0,0 +1,172 @@
+use anyhow::Result;
+use colored::*;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+    thread,
+};
+
+use crate::output::buffered;
+use crate::*;
+
+// Running autorebase on every repo in a directory, e.g. a set of sibling
+// clones that are worked on together.
+
+/// Find the git repositories in `dir` or below it. Repos inside other repos
+/// (e.g. submodules) and hidden directories are not searched.
+pub fn find_repos(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut repos = Vec::new();
+    find_repos_in(dir, &mut repos)?;
+    repos.sort();
+    Ok(repos)
+}
+
+fn find_repos_in(dir: &Path, repos: &mut Vec<PathBuf>) -> io::Result<()> {
+    // `.git` is a file in linked worktrees.
+    if dir.join(".git").exists() {
+        repos.push(dir.to_owned());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        // Don't follow symlinks; they could go round in circles.
+        if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+            find_repos_in(&entry.path(), repos)?;
+        }
+    }
+    Ok(())
+}
+
+/// The outcome of running autorebase on one repo.
+#[derive(Debug)]
+pub struct RepoOutcome {
+    pub path: PathBuf,
+    /// An error if autorebase failed completely for this repo.
+    pub outcome: Result<RunOutcome>,
+}
+
+/// The outcome of running autorebase on several repos.
+#[derive(Debug)]
+pub struct WorkspaceOutcome {
+    /// In the same order as the repos were given.
+    pub repos: Vec<RepoOutcome>,
+}
+
+/// Autorebase each of `repos` with the same options, `jobs` at a time. The
+/// target branch is found separately for each repo unless
+/// `options.onto_branch` is set.
+///
+/// When running more than one at a time the output for each repo is printed
+/// when it finishes. Errors are recorded in the returned `WorkspaceOutcome`
+/// and don't stop the other repos from being rebased.
+pub fn autorebase_repos(
+    repos: &[PathBuf],
+    options: &AutorebaseOptions,
+    jobs: usize,
+) -> WorkspaceOutcome {
+    // This sets an environment variable, which mustn't be done while other
+    // threads may be running git.
+    set_committer_date_to_now();
+
+    let autorebase_repo = |path: &Path| {
+        reportln!("{} {}", "• Repo".bold(), path.display().to_string().bold());
+        let outcome = autorebase(path, options);
+        if let Err(e) = &outcome {
+            reportln!("\n{} {:?}", "    - Error:".red(), e);
+        }
+        outcome
+    };
+
+    let outcomes: Vec<Result<RunOutcome>> = if jobs <= 1 {
+        repos.iter().map(|path| autorebase_repo(path)).collect()
+    } else {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..jobs.min(repos.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(path) = repos.get(index) else {
+                        break;
+                    };
+                    let (outcome, output) = buffered(|| autorebase_repo(path));
+                    eprint!("{}", output);
+                    results
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push((index, outcome));
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+        // Put them back in the original order.
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, outcome)| outcome).collect()
+    };
+
+    WorkspaceOutcome {
+        repos: repos
+            .iter()
+            .cloned()
+            .zip(outcomes)
+            .map(|(path, outcome)| RepoOutcome { path, outcome })
+            .collect(),
+    }
+}
+
+impl WorkspaceOutcome {
+    /// Did autorebase fail completely for any repo?
+    pub fn has_errors(&self) -> bool {
+        self.repos.iter().any(|r| r.outcome.is_err())
+    }
+
+    /// The outcomes for the repos that didn't fail completely.
+    pub fn outcomes(&self) -> impl Iterator<Item = &RunOutcome> {
+        self.repos.iter().filter_map(|r| r.outcome.as_ref().ok())
+    }
+
+    /// Print a table of the outcome for each repo. Paths are shown relative
+    /// to `base` if they are inside it.
+    pub fn print_summary(&self, base: &Path) {
+        eprintln!("• Summary:");
+        print_repo_outcomes(&self.repos, Some(base));
+    }
+}
+
+/// Print a row for each repo saying what happened to it, with paths relative
+/// to `base`, or the current directory if `base` is `None`.
+pub(crate) fn print_repo_outcomes(repos: &[RepoOutcome], base: Option<&Path>) {
+    let base = base.map_or_else(|| env::current_dir().unwrap_or_default(), Path::to_owned);
+    let name = |path: &Path| match path.strip_prefix(&base) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+        _ => path.display().to_string(),
+    };
+
+    let width = repos
+        .iter()
+        .map(|r| name(&r.path).chars().count())
+        .max()
+        .unwrap_or(0);
+
+    for repo in repos {
+        let summary = match &repo.outcome {
+            Ok(outcome) => format!("{}: {}", outcome.target_branch.blue(), outcome.counts()),
+            // The full error was printed with the rest of the output.
+            Err(e) => format!(
+                "{} {}",
+                "failed:".red(),
+                e.to_string().lines().next().unwrap_or_default()
+            ),
+        };
+        eprintln!(
+            "    {:width$}  {}",
+            name(&repo.path).bold(),
+            summary,
+            width = width
+        );
+    }
+}
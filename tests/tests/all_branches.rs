@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 
-use autorebase::autorebase;
+use autorebase::{autorebase, AutorebaseOptions};
 
 use crate::{commit_graph, utils::*};
 
@@ -23,8 +23,15 @@ fn with_include(include_all_branches: bool) -> BTreeMap<String, CommitGraphNode>
 
     print_git_log_graph(repo_dir);
 
-    autorebase(repo_dir, Some("master"), false, include_all_branches, None)
-        .expect("error autorebasing");
+    autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            include_non_local: include_all_branches,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
 
     print_git_log_graph(repo_dir);
 
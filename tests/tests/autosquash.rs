@@ -0,0 +1,140 @@
+use crate::utils::*;
+use autorebase::{autorebase, AutorebaseOptions, BranchOutcome};
+use git_commands::git;
+use std::path::Path;
+
+fn subjects(repo_dir: &Path, range: &str) -> Vec<String> {
+    let out = git(&["log", "--format=%s", range], repo_dir)
+        .expect("error getting log")
+        .stdout;
+    String::from_utf8_lossy(&out)
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+// A branch with a fixup commit that is behind master.
+fn autosquash_behind(autosquash: bool) -> Vec<String> {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(
+            commit("Add b").write("b.txt", "foo").child(
+                commit("Add c")
+                    .write("c.txt", "foo")
+                    .child(commit("fixup! Add b").write("b.txt", "fixed").branch("wip")),
+            ),
+        );
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            autosquash,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    let contents = git(&["show", "wip:b.txt"], repo_dir)
+        .expect("error showing file")
+        .stdout;
+    assert_eq!(String::from_utf8_lossy(&contents), "fixed");
+
+    subjects(repo_dir, "master..wip")
+}
+
+#[test]
+fn autosquash_enabled() {
+    assert_eq!(autosquash_behind(true), ["Add c", "Add b"]);
+}
+
+#[test]
+fn autosquash_disabled() {
+    assert_eq!(autosquash_behind(false), ["fixup! Add b", "Add c", "Add b"]);
+}
+
+// A branch that is already on master should still be squashed.
+#[test]
+fn autosquash_up_to_date() {
+    git_fixed_dates();
+
+    let root = commit("First").write("a.txt", "hello").child(
+        commit("Second")
+            .write("a.txt", "world")
+            .branch("master")
+            .child(
+                commit("Add b")
+                    .write("b.txt", "foo")
+                    .child(commit("fixup! Add b").write("b.txt", "fixed").branch("wip")),
+            ),
+    );
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            autosquash: true,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(subjects(repo_dir, "master..wip"), ["Add b"]);
+}
+
+// `squash!` commits are squashed without waiting for an editor to edit the
+// combined message.
+#[test]
+fn autosquash_squash_commit() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(
+            commit("Add b").write("b.txt", "foo").child(
+                commit("squash! Add b")
+                    .write("b.txt", "fixed")
+                    .branch("wip"),
+            ),
+        );
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            autosquash: true,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(subjects(repo_dir, "master..wip"), ["Add b"]);
+}
@@ -0,0 +1,94 @@
+use crate::utils::*;
+use git_commands::{GitBackend, Libgit2, Subprocess};
+
+// The native backend should give exactly the same answers as running `git`.
+#[test]
+fn backends_agree() {
+    git_fixed_dates();
+
+    let root = commit("First").write("a.txt", "hello").child(
+        commit("Second")
+            .write("a.txt", "world")
+            .branch("master")
+            .branch_with_upstream("other_main", "master")
+            .child(commit("Merged").id(1).write("c.txt", "merged"))
+            .child(
+                commit("WIP")
+                    .write("b.txt", "goodbye")
+                    .merge_parent(1)
+                    .branch("wip"),
+            ),
+    );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    // Check out `wip` in another worktree.
+    let worktree = tempfile::tempdir().expect("error creating temporary directory");
+    let worktree_path = worktree.path().join("wip");
+    git_commands::git(
+        &["worktree", "add", worktree_path.to_str().unwrap(), "wip"],
+        repo_dir,
+    )
+    .expect("error adding worktree");
+
+    print_git_log_graph(repo_dir);
+
+    for dir in [repo_dir, worktree_path.as_path()] {
+        assert_eq!(
+            Subprocess.branches(dir).unwrap(),
+            Libgit2.branches(dir).unwrap()
+        );
+        assert_eq!(
+            Subprocess.commit_list(dir, "master", "wip").unwrap(),
+            Libgit2.commit_list(dir, "master", "wip").unwrap()
+        );
+        assert_eq!(
+            Subprocess.merge_base(dir, "master", "wip").unwrap(),
+            Libgit2.merge_base(dir, "master", "wip").unwrap()
+        );
+        assert_eq!(
+            Subprocess.commit_hash(dir, "wip^").unwrap(),
+            Libgit2.commit_hash(dir, "wip^").unwrap()
+        );
+    }
+
+    // Branches with no common ancestor have no merge base, but bad revisions
+    // are still errors.
+    git_commands::git(&["switch", "--orphan", "unrelated"], repo_dir)
+        .expect("error switching branch");
+    git_commands::git(&["commit", "--allow-empty", "-m", "Unrelated"], repo_dir)
+        .expect("error committing");
+    git_commands::git(&["switch", "master"], repo_dir).expect("error switching branch");
+    for backend in [&Subprocess as &dyn GitBackend, &Libgit2] {
+        assert_eq!(
+            backend.merge_base(repo_dir, "master", "unrelated").unwrap(),
+            None
+        );
+        backend
+            .merge_base(repo_dir, "master", "nope")
+            .expect_err("merge base should fail");
+    }
+
+    // Ref updates only happen if the old value matches.
+    let master = get_branch_commit(repo_dir, "master");
+    let wip = get_branch_commit(repo_dir, "wip");
+    for backend in [&Subprocess as &dyn GitBackend, &Libgit2] {
+        backend
+            .update_ref(repo_dir, "refs/heads/other_main", &wip, Some(&wip), "test")
+            .expect_err("update should fail");
+        backend
+            .update_ref(
+                repo_dir,
+                "refs/heads/other_main",
+                &wip,
+                Some(&master),
+                "test",
+            )
+            .expect("update should succeed");
+        assert_eq!(get_branch_commit(repo_dir, "other_main"), wip);
+        backend
+            .update_ref(repo_dir, "refs/heads/other_main", &master, None, "test")
+            .expect("update should succeed");
+    }
+}
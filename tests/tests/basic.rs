@@ -1,5 +1,5 @@
 use crate::{commit_graph, utils::*};
-use autorebase::autorebase;
+use autorebase::{autorebase, AutorebaseOptions};
 
 // Test building a repo using `build_repo`.
 #[test]
@@ -65,10 +65,11 @@ fn basic_autorebase(slow_conflict_detection: bool) {
 
     autorebase(
         repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
@@ -1,5 +1,5 @@
 use crate::{commit_graph, utils::*};
-use autorebase::autorebase;
+use autorebase::{autorebase, AutorebaseOptions, BranchOutcome};
 
 // Single branch that cannot be rebased all the way to `master` commit due to conflicts.
 #[test]
@@ -34,15 +34,23 @@ fn conflict(slow_conflict_detection: bool) {
 
     print_git_log_graph(repo_dir);
 
-    autorebase(
+    let outcome = autorebase(
         repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Conflicts)
+    ));
+    assert!(outcome.has_stuck());
+    assert!(!outcome.has_failures());
+
     print_git_log_graph(repo_dir);
 
     let graph = get_repo_graph(repo_dir).expect("error getting repo graph");
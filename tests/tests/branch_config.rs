@@ -0,0 +1,211 @@
+use crate::utils::*;
+use autorebase::{status, AutorebaseOptions, BranchOutcome, SkipReason};
+use git_commands::git;
+
+fn set_config(repo_dir: &std::path::Path, key: &str, value: &str) {
+    git(&["config", key, value], repo_dir).expect("error setting config");
+}
+
+// Branches can opt out, opt in despite having an upstream, or choose their
+// own target, with `git config`.
+#[test]
+fn branch_config() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second")
+                .write("b.txt", "foo")
+                .branch("master")
+                .child(commit("Develop").write("d.txt", "baz").branch("develop")),
+        )
+        .child(commit("Frozen").write("c.txt", "bar").branch("frozen.1"))
+        .child(commit("Tracked").write("e.txt", "qux").branch("tracked"))
+        .child(commit("Feature").write("f.txt", "quux").branch("feature"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    set_config(repo_dir, "branch.frozen.1.autorebase", "false");
+    set_config(repo_dir, "branch.tracked.remote", ".");
+    set_config(repo_dir, "branch.tracked.merge", "refs/heads/master");
+    set_config(repo_dir, "branch.tracked.autorebase", "yes");
+    set_config(repo_dir, "branch.feature.autorebaseOnto", "develop");
+    set_config(repo_dir, "branch.feature.description", "Does a thing");
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    let repo_status = status(repo_dir, &options).expect("error getting status");
+    let feature = repo_status
+        .branches
+        .iter()
+        .find(|b| b.branch == "feature")
+        .unwrap();
+    assert_eq!(feature.onto.as_deref(), Some("develop"));
+    assert_eq!(feature.description.as_deref(), Some("Does a thing"));
+    assert_eq!(feature.behind, Some(2));
+
+    let frozen_commit = get_branch_commit(repo_dir, "frozen.1");
+
+    let outcome = run_autorebase(repo_dir, &options);
+
+    assert!(matches!(
+        outcome.outcome("frozen.1"),
+        Some(BranchOutcome::Skipped(SkipReason::DisabledInConfig))
+    ));
+    assert_eq!(get_branch_commit(repo_dir, "frozen.1"), frozen_commit);
+
+    assert!(matches!(
+        outcome.outcome("tracked"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "tracked^"),
+        get_branch_commit(repo_dir, "master")
+    );
+
+    assert!(matches!(
+        outcome.outcome("feature"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "feature^"),
+        get_branch_commit(repo_dir, "develop")
+    );
+}
+
+// A branch rebased onto another branch goes onto where that branch ends up,
+// even if it would be rebased first in alphabetical order.
+#[test]
+fn own_target_rebased_first() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(
+            commit("Base")
+                .write("c.txt", "bar")
+                .branch("b-base")
+                .child(commit("Feature").write("d.txt", "baz").branch("a-feature")),
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    set_config(repo_dir, "branch.a-feature.autorebaseOnto", "b-base");
+
+    let outcome = run_autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(outcome.branches[0].branch, "b-base");
+    assert_eq!(
+        get_branch_commit(repo_dir, "b-base^"),
+        get_branch_commit(repo_dir, "master")
+    );
+    assert_eq!(
+        get_branch_commit(repo_dir, "a-feature^"),
+        get_branch_commit(repo_dir, "b-base")
+    );
+}
+
+// A branch whose target doesn't exist doesn't stop the others' status being
+// shown.
+#[test]
+fn missing_own_target_status() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(commit("WIP").write("c.txt", "bar").branch("wip"))
+        .child(commit("Feature").write("d.txt", "baz").branch("feature"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    set_config(repo_dir, "branch.feature.autorebaseOnto", "nope");
+
+    let repo_status = status(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error getting status");
+
+    for branch in &repo_status.branches {
+        if branch.branch == "feature" {
+            assert!(branch.error.as_deref().unwrap().contains("nope"));
+            assert_eq!(branch.behind, None);
+        } else {
+            assert_eq!(branch.error, None);
+            assert!(branch.behind.is_some());
+        }
+    }
+}
+
+// A bad `branch.<name>.autorebase` value is an error for that branch only.
+// Integers are booleans too, like in git.
+#[test]
+fn invalid_autorebase_value() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(commit("Bad").write("c.txt", "bar").branch("bad"))
+        .child(commit("Tracked").write("d.txt", "baz").branch("tracked"))
+        .child(commit("Frozen").write("e.txt", "qux").branch("frozen"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    set_config(repo_dir, "branch.bad.autorebase", "maybe");
+    set_config(repo_dir, "branch.tracked.remote", ".");
+    set_config(repo_dir, "branch.tracked.merge", "refs/heads/master");
+    set_config(repo_dir, "branch.tracked.autorebase", "2");
+    set_config(repo_dir, "branch.frozen.autorebase", "0");
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    let repo_status = status(repo_dir, &options).expect("error getting status");
+    for branch in &repo_status.branches {
+        if branch.branch == "bad" {
+            assert!(branch.error.as_deref().unwrap().contains("maybe"));
+        } else {
+            assert_eq!(branch.error, None);
+        }
+    }
+
+    let bad_commit = get_branch_commit(repo_dir, "bad");
+
+    let outcome = run_autorebase(repo_dir, &options);
+
+    assert!(matches!(
+        outcome.outcome("bad"),
+        Some(BranchOutcome::Failed(_))
+    ));
+    assert_eq!(get_branch_commit(repo_dir, "bad"), bad_commit);
+    assert!(matches!(
+        outcome.outcome("tracked"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert!(matches!(
+        outcome.outcome("frozen"),
+        Some(BranchOutcome::Skipped(SkipReason::DisabledInConfig))
+    ));
+}
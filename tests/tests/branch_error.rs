@@ -0,0 +1,115 @@
+use crate::utils::*;
+use autorebase::{autorebase, get_git_common_dir, AutorebaseOptions, BranchOutcome};
+use std::{fs, os::unix::fs::PermissionsExt};
+
+// Make rebasing one branch fail with an error (not a conflict) and check that
+// the other branch is still rebased.
+#[test]
+fn branch_error_continues() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("Bad").write("b.txt", "foo").branch("bad"))
+        .child(commit("Good").write("c.txt", "bar").branch("good"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let bad_before = get_branch_commit(repo_dir, "bad");
+    let good_before = get_branch_commit(repo_dir, "good");
+
+    // The exit code of the post-checkout hook becomes the exit code of
+    // `git switch`, so this makes checking out `bad` fail.
+    let hook_path = get_git_common_dir(repo_dir)
+        .expect("error getting git dir")
+        .join("hooks/post-checkout");
+    fs::create_dir_all(hook_path.parent().unwrap()).expect("error creating hooks dir");
+    fs::write(
+        &hook_path,
+        format!(
+            "#!/bin/sh\n[ \"$(git rev-parse HEAD)\" = {} ] && exit 1\nexit 0\n",
+            bad_before
+        ),
+    )
+    .expect("error writing hook");
+    fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755))
+        .expect("error setting hook permissions");
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(outcome.has_failures());
+    assert!(matches!(
+        outcome.outcome("bad"),
+        Some(BranchOutcome::Failed(_))
+    ));
+    assert!(matches!(
+        outcome.outcome("good"),
+        Some(BranchOutcome::Rebased)
+    ));
+
+    assert_eq!(get_branch_commit(repo_dir, "bad"), bad_before);
+    assert_ne!(get_branch_commit(repo_dir, "good"), good_before);
+}
+
+// A lock file left behind by a crashed git process makes the rebase fail. That
+// isn't a conflict so the branch shouldn't be marked as stuck.
+#[test]
+fn ref_locked_not_stuck() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("Bad").write("b.txt", "foo").branch("bad"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let lock_path = get_git_common_dir(repo_dir)
+        .expect("error getting git dir")
+        .join("refs/heads/bad.lock");
+    fs::write(&lock_path, "").expect("error writing lock file");
+
+    let bad_before = get_branch_commit(repo_dir, "bad");
+
+    // The slow method is simpler so there's nothing else that could fail.
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        slow_conflict_detection: true,
+        ..Default::default()
+    };
+
+    let outcome = autorebase(repo_dir, &options).expect("error autorebasing");
+
+    assert!(matches!(
+        outcome.outcome("bad"),
+        Some(BranchOutcome::Failed(_))
+    ));
+    assert!(!outcome.has_stuck());
+    assert_eq!(get_branch_commit(repo_dir, "bad"), bad_before);
+
+    // Once the lock is gone it should be rebased as normal.
+    fs::remove_file(&lock_path).expect("error removing lock file");
+
+    let outcome = autorebase(repo_dir, &options).expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(matches!(
+        outcome.outcome("bad"),
+        Some(BranchOutcome::Rebased)
+    ));
+}
@@ -0,0 +1,74 @@
+use crate::utils::*;
+use autorebase::{status, AutorebaseOptions, SkipReason};
+use git_commands::git;
+use std::time::Duration;
+
+// Check branches are skipped based on their author and age.
+#[test]
+fn select_by_author_and_age() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("WIP").write("b.txt", "foo").branch("mine"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    // Add a branch with a commit by somebody else.
+    let theirs = git(
+        &[
+            "-c",
+            "user.email=other@example.com",
+            "commit-tree",
+            "-p",
+            "mine",
+            "-m",
+            "Theirs",
+            "mine^{tree}",
+        ],
+        repo_dir,
+    )
+    .expect("error committing")
+    .stdout;
+    let theirs = String::from_utf8_lossy(&theirs);
+    git(&["branch", "theirs", theirs.trim()], repo_dir).expect("error creating branch");
+
+    let skip_reasons = |options: &AutorebaseOptions| -> Vec<(String, Option<SkipReason>)> {
+        status(repo_dir, options)
+            .expect("error getting status")
+            .branches
+            .into_iter()
+            .map(|b| (b.branch, b.skip_reason))
+            .collect()
+    };
+
+    let mine_only = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        only_mine: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        skip_reasons(&mine_only),
+        [
+            ("mine".to_owned(), None),
+            ("theirs".to_owned(), Some(SkipReason::NotMine)),
+        ]
+    );
+
+    // The commits are all from 1970 thanks to `git_fixed_dates()`.
+    let recent_only = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        max_age: Some(Duration::from_secs(24 * 60 * 60)),
+        ..Default::default()
+    };
+    assert_eq!(
+        skip_reasons(&recent_only),
+        [
+            ("mine".to_owned(), Some(SkipReason::TooOld)),
+            ("theirs".to_owned(), Some(SkipReason::TooOld)),
+        ]
+    );
+}
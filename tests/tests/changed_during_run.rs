@@ -0,0 +1,137 @@
+use crate::utils::*;
+use autorebase::{autorebase, get_git_common_dir, AutorebaseOptions, BranchOutcome};
+use git_commands::git;
+use std::{fs, os::unix::fs::PermissionsExt};
+
+// Commit to a branch while it is being rebased and check that the commit
+// isn't lost.
+#[test]
+fn changed_during_run() {
+    git_fixed_dates();
+
+    // `Same` is already on master so that `git replay` isn't used; it doesn't
+    // run any hooks.
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Same (cherry-picked)")
+                .write("c.txt", "same")
+                .child(commit("Second").write("a.txt", "world").branch("master")),
+        )
+        .child(
+            commit("Same")
+                .write("c.txt", "same")
+                .child(commit("WIP").write("b.txt", "foo").branch("wip")),
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    // Commit to `wip` once the rebase has finished but before autorebase has
+    // moved the branch.
+    let git_common_dir = get_git_common_dir(repo_dir).expect("error getting git dir");
+    let hook_path = git_common_dir.join("hooks/post-rewrite");
+    fs::create_dir_all(hook_path.parent().unwrap()).expect("error creating hooks dir");
+    fs::write(
+        &hook_path,
+        "#!/bin/sh\n\
+         commit=$(git commit-tree -p wip -m Concurrent 'wip^{tree}')\n\
+         git update-ref refs/heads/wip \"$commit\"\n",
+    )
+    .expect("error writing hook");
+    fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755))
+        .expect("error setting hook permissions");
+
+    let wip_before = get_branch_commit(repo_dir, "wip");
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::ChangedDuringRun)
+    ));
+    assert!(!outcome.has_failures());
+    assert!(!outcome.has_stuck());
+
+    // The concurrent commit is still there, on top of the original branch.
+    assert_eq!(get_branch_commit(repo_dir, "wip^"), wip_before);
+    let subject = git(&["log", "-1", "--format=%s", "wip"], repo_dir).expect("error running log");
+    assert_eq!(
+        String::from_utf8_lossy(&subject.stdout).trim(),
+        "Concurrent"
+    );
+
+    // It is rebased next time.
+    fs::remove_file(&hook_path).expect("error removing hook");
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip~2"),
+        get_branch_commit(repo_dir, "master")
+    );
+}
+
+// Delete a branch while it is being rebased, when the rebase doesn't get
+// anywhere because of conflicts.
+#[test]
+fn deleted_during_run() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("WIP").write("a.txt", "earth").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let git_common_dir = get_git_common_dir(repo_dir).expect("error getting git dir");
+    let hook_path = git_common_dir.join("hooks/pre-rebase");
+    fs::create_dir_all(hook_path.parent().unwrap()).expect("error creating hooks dir");
+    fs::write(
+        &hook_path,
+        "#!/bin/sh\n\
+         git update-ref -d refs/heads/wip || true\n",
+    )
+    .expect("error writing hook");
+    fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755))
+        .expect("error setting hook permissions");
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::ChangedDuringRun)
+    ));
+    assert!(!outcome.has_failures());
+}
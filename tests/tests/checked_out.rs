@@ -1,5 +1,5 @@
 use crate::{commit_graph, utils::*};
-use autorebase::autorebase;
+use autorebase::{autorebase, AutorebaseOptions};
 use std::fs;
 
 // Check we can rebase with the current checked out branch.
@@ -29,10 +29,11 @@ fn checkedout_clean(slow_conflict_detection: bool) {
 
     autorebase(
         repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
@@ -97,10 +98,11 @@ fn checkedout_dirty(slow_conflict_detection: bool) {
 
     autorebase(
         repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
@@ -172,10 +174,11 @@ fn checked_out_conflict(slow_conflict_detection: bool) {
 
     autorebase(
         repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
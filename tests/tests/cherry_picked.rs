@@ -0,0 +1,65 @@
+use crate::utils::*;
+use autorebase::{autorebase, AutorebaseOptions, BranchOutcome};
+
+// A branch where one commit has already been cherry-picked onto `master`, and
+// another one conflicts with `master`. It should be rebased up to the conflicting
+// commit, dropping the one that landed.
+#[test]
+fn cherry_picked_slow() {
+    cherry_picked(true);
+}
+
+#[test]
+fn cherry_picked_fast() {
+    cherry_picked(false);
+}
+
+fn cherry_picked(slow_conflict_detection: bool) {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "1")
+        .write("b.txt", "1")
+        .child(
+            commit("Landed").write("a.txt", "2").child(
+                commit("Change a")
+                    .write("a.txt", "3")
+                    .child(commit("Change b").write("b.txt", "5").branch("master")),
+            ),
+        )
+        .child(
+            // Same change but a different commit.
+            commit("Land this")
+                .write("a.txt", "2")
+                .child(commit("WIP").write("b.txt", "2").branch("wip")),
+        );
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    print_git_log_graph(repo_dir);
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Conflicts)
+    ));
+
+    // `wip` should be on "Change a" with only the "WIP" commit left.
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master^")
+    );
+}
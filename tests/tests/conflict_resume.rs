@@ -1,5 +1,5 @@
 use crate::{commit_graph, utils::*};
-use autorebase::autorebase;
+use autorebase::{autorebase, AutorebaseOptions};
 use git_commands::git;
 use std::fs;
 
@@ -42,10 +42,11 @@ fn conflict_resume(slow_conflict_detection: bool) {
 
     autorebase(
         repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
@@ -105,7 +106,15 @@ fn conflict_resume(slow_conflict_detection: bool) {
 
     print_git_log_graph(repo_dir);
 
-    autorebase(repo_dir, Some("master"), true, false, None).expect("error autorebasing");
+    autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection: true,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
 
     print_git_log_graph(repo_dir);
 
@@ -174,7 +183,15 @@ fn conflict_resume(slow_conflict_detection: bool) {
 
     print_git_log_graph(repo_dir);
 
-    autorebase(repo_dir, Some("master"), true, false, None).expect("error autorebasing");
+    autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection: true,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
 
     print_git_log_graph(repo_dir);
 
@@ -1,5 +1,5 @@
 use crate::{commit_graph, utils::*};
-use autorebase::autorebase;
+use autorebase::{autorebase, AutorebaseOptions};
 use tempfile::tempdir;
 
 // Test running autorebase from another worktree.
@@ -46,10 +46,11 @@ fn from_another_worktree(slow_conflict_detection: bool) {
     // Now autorebase from the other worktree dir.
     autorebase(
         &another_worktree_repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
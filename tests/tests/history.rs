@@ -0,0 +1,138 @@
+use crate::utils::*;
+use autorebase::{autorebase, get_git_common_dir, rebase_log, AutorebaseOptions, RebaseKind};
+use git_commands::git;
+
+// Check that rebases are recorded in the reflog and can be listed.
+#[test]
+fn rebase_log_lists_runs() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second")
+                .write("b.txt", "foo")
+                .child(commit("Third").write("a.txt", "world").branch("master")),
+        )
+        .child(commit("Feature").write("c.txt", "bar").branch("feature"))
+        .child(commit("WIP").write("d.txt", "baz").branch("wip"))
+        .child(commit("Conflict").write("a.txt", "earth").branch("stuck"));
+
+    // `feature` is checked out so `git rebase` updates it; the others are
+    // rebased in autorebase's worktree and moved afterwards.
+    let repo = build_repo(&root, Some("feature"));
+    let repo_dir = repo.path();
+
+    let before = |branch| get_branch_commit(repo_dir, branch);
+    let (feature_before, wip_before, stuck_before) =
+        (before("feature"), before("wip"), before("stuck"));
+
+    // Nothing has been rebased yet.
+    assert!(rebase_log(repo_dir).expect("error reading log").is_empty());
+
+    autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    fix_reflog_dates(repo_dir);
+
+    let runs = rebase_log(repo_dir).expect("error reading log");
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].time, 1000000000);
+
+    let rebases: Vec<_> = runs[0]
+        .rebases
+        .iter()
+        .map(|r| {
+            (
+                r.branch.as_str(),
+                r.kind,
+                r.onto.clone(),
+                r.old_commit.clone(),
+                r.new_commit.clone(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        rebases,
+        [
+            (
+                "feature",
+                RebaseKind::Full,
+                get_branch_commit(repo_dir, "master"),
+                Some(feature_before),
+                get_branch_commit(repo_dir, "feature"),
+            ),
+            (
+                "stuck",
+                RebaseKind::Partial,
+                get_branch_commit(repo_dir, "master^"),
+                Some(stuck_before),
+                get_branch_commit(repo_dir, "stuck"),
+            ),
+            (
+                "wip",
+                RebaseKind::Full,
+                get_branch_commit(repo_dir, "master"),
+                Some(wip_before),
+                get_branch_commit(repo_dir, "wip"),
+            ),
+        ]
+    );
+}
+
+// Reflog entries are grouped by run, even if their times differ (e.g. when
+// libgit2 moves the branch) or two runs happen at the same time.
+#[test]
+fn rebase_log_groups_by_run() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(commit("Feature").write("c.txt", "bar").branch("feature"))
+        .child(commit("WIP").write("d.txt", "baz").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    autorebase(repo_dir, &options).expect("error autorebasing");
+    fix_reflog_dates(repo_dir);
+
+    // Make one of the entries a second later.
+    let common_dir = get_git_common_dir(repo_dir).expect("error getting git dir");
+    let wip_log = common_dir.join("logs/refs/heads/wip");
+    let log = std::fs::read_to_string(&wip_log).expect("error reading reflog");
+    std::fs::write(
+        &wip_log,
+        log.replace("> 1000000000 +0000\t", "> 1000000001 +0000\t"),
+    )
+    .expect("error writing reflog");
+
+    let runs = rebase_log(repo_dir).expect("error reading log");
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].rebases.len(), 2);
+    assert_eq!(runs[0].time, 1000000001);
+
+    // Another run at the same time is listed separately.
+    git(&["commit", "--allow-empty", "-m", "Third"], repo_dir).expect("error committing");
+    autorebase(repo_dir, &options).expect("error autorebasing");
+    fix_reflog_dates(repo_dir);
+
+    let runs = rebase_log(repo_dir).expect("error reading log");
+    assert_eq!(runs.len(), 2);
+    assert!(runs.iter().all(|run| run.rebases.len() == 2));
+}
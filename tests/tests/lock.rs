@@ -0,0 +1,141 @@
+use crate::utils::*;
+use autorebase::{autorebase, get_git_common_dir, AutorebaseOptions, RepoLock};
+use std::{
+    fs,
+    time::{Duration, SystemTime},
+};
+
+// Check that autorebase refuses to run while another instance holds the lock,
+// and works again once it has been released.
+#[test]
+fn lock_held() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("WIP").write("b.txt", "foo").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    let git_common_dir = get_git_common_dir(repo_dir).expect("error getting git dir");
+
+    let lock = RepoLock::acquire(&git_common_dir, false).expect("error taking lock");
+
+    let err = autorebase(repo_dir, &options).expect_err("autorebase should fail when locked");
+    assert!(err.to_string().contains("Another autorebase is running"));
+
+    drop(lock);
+
+    autorebase(repo_dir, &options).expect("error autorebasing");
+}
+
+// A lock file that is very old should be ignored.
+#[test]
+fn lock_stale() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("WIP").write("b.txt", "foo").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let git_common_dir = get_git_common_dir(repo_dir).expect("error getting git dir");
+    let lock_path = git_common_dir.join("autorebase/autorebase.lock");
+    fs::create_dir_all(lock_path.parent().unwrap()).expect("error creating lock dir");
+    fs::write(
+        &lock_path,
+        "pid = 1\nhostname = \"some-other-host\"\ncreated = 0\n",
+    )
+    .expect("error writing lock file");
+
+    autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    assert!(!lock_path.exists());
+}
+
+// A lock file that can't be read may be about to be replaced, so it is only
+// removed once it is old.
+#[test]
+fn lock_unreadable() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("WIP").write("b.txt", "foo").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    let git_common_dir = get_git_common_dir(repo_dir).expect("error getting git dir");
+    let lock_path = git_common_dir.join("autorebase/autorebase.lock");
+    fs::create_dir_all(lock_path.parent().unwrap()).expect("error creating lock dir");
+    fs::write(&lock_path, "pid = ").expect("error writing lock file");
+
+    let err = autorebase(repo_dir, &options).expect_err("autorebase should fail when locked");
+    assert!(err.to_string().contains("Another autorebase is running"));
+    assert!(lock_path.exists());
+
+    fs::File::options()
+        .write(true)
+        .open(&lock_path)
+        .and_then(|file| file.set_modified(SystemTime::now() - Duration::from_secs(60)))
+        .expect("error setting lock file time");
+
+    autorebase(repo_dir, &options).expect("error autorebasing");
+
+    assert!(!lock_path.exists());
+}
+
+// If another process decides our lock is stale and takes it over, releasing
+// ours doesn't remove theirs.
+#[test]
+fn lock_taken_over() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let git_common_dir = get_git_common_dir(repo_dir).expect("error getting git dir");
+    let lock_path = git_common_dir.join("autorebase/autorebase.lock");
+
+    let lock = RepoLock::acquire(&git_common_dir, false).expect("error taking lock");
+    let theirs = "pid = 1\nhostname = \"some-other-host\"\ncreated = 0\n";
+    fs::write(&lock_path, theirs).expect("error writing lock file");
+    drop(lock);
+
+    assert_eq!(
+        fs::read_to_string(&lock_path).expect("error reading lock file"),
+        theirs
+    );
+}
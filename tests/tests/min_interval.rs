@@ -0,0 +1,145 @@
+use crate::utils::*;
+use autorebase::{autorebase, status, AutorebaseOptions, BranchOutcome};
+use git_commands::git;
+use std::{path::Path, time::Duration};
+
+fn add_master_commits(repo_dir: &Path, count: usize) {
+    for i in 0..count {
+        git(
+            &["commit", "--allow-empty", "-m", &format!("More {}", i)],
+            repo_dir,
+        )
+        .expect("error committing");
+    }
+}
+
+// Branches that were rebased recently are left alone until the target has
+// moved on far enough.
+#[test]
+fn min_interval() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(commit("WIP").write("c.txt", "bar").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        min_interval: Some(Duration::from_secs(4 * 60 * 60)),
+        min_interval_commits: Some(2),
+        ..Default::default()
+    };
+
+    // It has never been rebased, so it is rebased now.
+    assert!(matches!(
+        run_autorebase(repo_dir, &options).outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+
+    add_master_commits(repo_dir, 2);
+
+    let repo_status = status(repo_dir, &options).expect("error getting status");
+    assert!(repo_status.branches[0].rebased_recently);
+
+    let wip_commit = get_branch_commit(repo_dir, "wip");
+    assert!(matches!(
+        run_autorebase(repo_dir, &options).outcome("wip"),
+        Some(BranchOutcome::RebasedRecently)
+    ));
+    assert_eq!(get_branch_commit(repo_dir, "wip"), wip_commit);
+
+    // Without the interval it is rebased as usual.
+    assert!(matches!(
+        run_autorebase(
+            repo_dir,
+            &AutorebaseOptions {
+                onto_branch: Some("master".to_owned()),
+                ..Default::default()
+            }
+        )
+        .outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+
+    // Once master is far enough ahead it is rebased anyway.
+    add_master_commits(repo_dir, 3);
+    assert!(matches!(
+        run_autorebase(repo_dir, &options).outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master")
+    );
+}
+
+// A branch that got stuck on its first commit hasn't moved, so it doesn't
+// count as rebased.
+#[test]
+fn min_interval_not_moved() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("WIP").write("a.txt", "earth").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        min_interval: Some(Duration::from_secs(4 * 60 * 60)),
+        ..Default::default()
+    };
+
+    let wip_commit = get_branch_commit(repo_dir, "wip");
+    assert!(matches!(
+        run_autorebase(repo_dir, &options).outcome("wip"),
+        Some(BranchOutcome::Conflicts)
+    ));
+    assert_eq!(get_branch_commit(repo_dir, "wip"), wip_commit);
+
+    let repo_status = status(repo_dir, &options).expect("error getting status");
+    assert!(!repo_status.branches[0].rebased_recently);
+}
+
+// When a branch is deleted we forget when it was rebased, so a new branch
+// with the same name isn't left alone.
+#[test]
+fn min_interval_deleted_branch() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(commit("WIP").write("c.txt", "bar").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        min_interval: Some(Duration::from_secs(4 * 60 * 60)),
+        ..Default::default()
+    };
+
+    let wip_commit = get_branch_commit(repo_dir, "wip");
+    assert!(matches!(
+        run_autorebase(repo_dir, &options).outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+
+    git(&["branch", "-D", "wip"], repo_dir).expect("error deleting branch");
+    autorebase(repo_dir, &options).expect("error autorebasing");
+
+    git(&["branch", "wip", &wip_commit], repo_dir).expect("error creating branch");
+    assert!(matches!(
+        run_autorebase(repo_dir, &options).outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+}
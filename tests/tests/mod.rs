@@ -1,9 +1,34 @@
 mod all_branches;
+mod autosquash;
+#[cfg(feature = "libgit2")]
+mod backend;
 mod basic;
 mod basic_conflict;
+mod branch_config;
+#[cfg(unix)]
+mod branch_error;
+mod branch_selection;
+#[cfg(unix)]
+mod changed_during_run;
 mod checked_out;
+mod cherry_picked;
 mod conflict_resume;
 mod from_another_worktree;
+mod history;
+mod lock;
+mod min_interval;
 mod multiple_branches;
 mod multiple_refs_on_branch;
+mod quality_check;
 mod random;
+mod replay;
+#[cfg(unix)]
+mod resolve;
+mod scratch_worktree;
+mod signing;
+mod status;
+mod submodules;
+mod target_commit;
+mod target_pull;
+mod trace;
+mod workspace;
@@ -1,5 +1,5 @@
 use crate::{commit_graph, utils::*};
-use autorebase::autorebase;
+use autorebase::{autorebase, AutorebaseOptions};
 
 // Basic test but there is more than one branch that needs to be rebased.
 #[test]
@@ -29,10 +29,11 @@ fn multiple_branches(slow_conflict_detection: bool) {
 
     autorebase(
         repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
@@ -0,0 +1,195 @@
+use crate::utils::*;
+use autorebase::{status, AutorebaseOptions, BranchOutcome, QualityCheck};
+use git_commands::git;
+
+#[test]
+fn quality_check_notes() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second").write("b.txt", "foo").child(
+                commit("Third")
+                    .write("b.txt", "bar")
+                    .child(commit("Broken").write("a.txt", "oops").branch("master")),
+            ),
+        )
+        .child(commit("WIP").write("c.txt", "baz").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        quality_check: Some("notes:ci".parse().expect("error parsing check")),
+        ..Default::default()
+    };
+
+    // Nothing has passed yet.
+    let wip_before = get_branch_commit(repo_dir, "wip");
+    let outcome = run_autorebase(repo_dir, &options);
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::UpToDate)
+    ));
+    assert_eq!(get_branch_commit(repo_dir, "wip"), wip_before);
+
+    for commit in ["master~2", "master^"] {
+        git(
+            &["notes", "--ref", "ci", "add", "-m", "passed", commit],
+            repo_dir,
+        )
+        .expect("error adding note");
+    }
+
+    let outcome = run_autorebase(repo_dir, &options);
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master^")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn quality_check_command() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second").write("b.txt", "foo").child(
+                commit("Third")
+                    .write("b.txt", "bar")
+                    .child(commit("Broken").write("a.txt", "oops").branch("master")),
+            ),
+        )
+        .child(commit("WIP").write("c.txt", "baz").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let outcome = run_autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            quality_check: Some(
+                r#"command:test "$AUTOREBASE_COMMIT" != "$(git rev-parse master)""#
+                    .parse()
+                    .expect("error parsing check"),
+            ),
+            ..Default::default()
+        },
+    );
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master^")
+    );
+}
+
+// Branches with `branch.<name>.autorebaseOnto` aren't rebased onto the target
+// branch, so its quality check doesn't apply to them.
+#[test]
+fn quality_check_own_target() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second").write("b.txt", "foo").child(
+                commit("Third")
+                    .write("b.txt", "bar")
+                    .child(commit("Broken").write("a.txt", "oops").branch("master")),
+            ),
+        )
+        .child(commit("WIP").write("c.txt", "baz").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    git(&["branch", "develop", "master"], repo_dir).expect("error creating branch");
+    git(
+        &["config", "branch.wip.autorebaseOnto", "develop"],
+        repo_dir,
+    )
+    .expect("error setting config");
+
+    let outcome = run_autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            quality_check: Some("notes:ci".parse().expect("error parsing check")),
+            ..Default::default()
+        },
+    );
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "develop")
+    );
+}
+
+// Status counts commits the same way, so it agrees with what would happen.
+#[test]
+fn quality_check_status() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second").write("b.txt", "foo").child(
+                commit("Third")
+                    .write("b.txt", "bar")
+                    .child(commit("Broken").write("a.txt", "oops").branch("master")),
+            ),
+        )
+        .child(commit("WIP").write("c.txt", "baz").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        quality_check: Some("notes:ci".parse().expect("error parsing check")),
+        ..Default::default()
+    };
+    let behind = || {
+        let repo_status = status(repo_dir, &options).expect("error getting status");
+        repo_status.branches[0].behind
+    };
+
+    // Nothing has passed yet.
+    assert_eq!(behind(), Some(0));
+
+    git(
+        &["notes", "--ref", "ci", "add", "-m", "passed", "master^"],
+        repo_dir,
+    )
+    .expect("error adding note");
+    assert_eq!(behind(), Some(2));
+}
+
+#[test]
+fn parse_quality_check() {
+    assert_eq!(
+        "notes:ci".parse::<QualityCheck>(),
+        Ok(QualityCheck::Notes("ci".to_owned()))
+    );
+    assert_eq!(
+        "command:ci-status --passed".parse::<QualityCheck>(),
+        Ok(QualityCheck::Command("ci-status --passed".to_owned()))
+    );
+    assert!("ci".parse::<QualityCheck>().is_err());
+    assert!("notes:".parse::<QualityCheck>().is_err());
+}
@@ -1,5 +1,5 @@
 use crate::utils::*;
-use autorebase::autorebase;
+use autorebase::{autorebase, AutorebaseOptions};
 
 // Test randomly generated repos.
 #[test]
@@ -25,10 +25,11 @@ fn random_test(slow_conflict_detection: bool) {
 
     autorebase(
         repo_dir,
-        Some("master"),
-        slow_conflict_detection,
-        false,
-        None,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            slow_conflict_detection,
+            ..Default::default()
+        },
     )
     .expect("error autorebasing");
 
@@ -0,0 +1,106 @@
+use crate::utils::*;
+use autorebase::{autorebase, git_version, AutorebaseOptions, BranchOutcome};
+use git_commands::git;
+use std::path::Path;
+
+/// Fail clearly if `git replay` isn't available. It was added in Git 2.44.
+fn require_git_replay() {
+    let version = git_version().expect("error getting git version");
+    assert!(
+        version.as_slice() >= &[2, 44],
+        "git replay is not available in Git {:?}",
+        version
+    );
+}
+
+fn reflog_subject(repo_dir: &Path, branch: &str) -> String {
+    let output =
+        git(&["reflog", "-1", "--format=%gs", branch], repo_dir).expect("error reading reflog");
+    String::from_utf8(output.stdout)
+        .expect("reflog is not unicode")
+        .trim()
+        .to_owned()
+}
+
+// Branches that aren't checked out are rebased without a checkout. The
+// branch is still moved by us, not by `git replay` itself (which it does by
+// default since Git 2.52), so it isn't overwritten if it changed meanwhile.
+#[test]
+#[ignore = "needs Git 2.44 or later for git replay; run with --ignored"]
+fn replay_without_checkout() {
+    require_git_replay();
+
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(
+            commit("WIP 1")
+                .write("b.txt", "foo")
+                .child(commit("WIP 2").write("b.txt", "bar").branch("wip")),
+        );
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip~2"),
+        get_branch_commit(repo_dir, "master")
+    );
+    fix_reflog_dates(repo_dir);
+    assert!(reflog_subject(repo_dir, "wip").starts_with("autorebase: full rebase onto"));
+}
+
+// Conflicts fall back to the normal rebase, which finds how far it can get.
+#[test]
+fn replay_conflict_falls_back() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second")
+                .write("b.txt", "foo")
+                .child(commit("Third").write("a.txt", "world").branch("master")),
+        )
+        .child(commit("WIP").write("a.txt", "earth").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Conflicts)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master^")
+    );
+}
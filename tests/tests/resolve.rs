@@ -0,0 +1,110 @@
+use crate::utils::*;
+use autorebase::{autorebase, get_git_common_dir, resolve, AutorebaseOptions, ResolveOptions};
+use git_commands::git;
+use std::{fs, path::Path};
+
+// Is `branch` marked as stuck in the conflicts file?
+fn is_stuck(conflicts_path: &Path, branch: &str) -> bool {
+    let conflicts: toml::Table =
+        toml::from_str(&fs::read_to_string(conflicts_path).expect("error reading conflicts"))
+            .expect("error parsing conflicts");
+    conflicts["branches"]
+        .as_table()
+        .is_some_and(|branches| branches.contains_key(branch))
+}
+
+// Resolve a stuck branch using a scripted "user", then check that it is
+// rebased onto the conflicting commit and no longer marked as stuck.
+#[test]
+fn resolve_conflict() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second").write("a.txt", "world").child(
+                commit("Third")
+                    .write("b.txt", "and")
+                    .child(commit("Fourth").write("b.txt", "others").branch("master")),
+            ),
+        )
+        .child(commit("WIP").write("b.txt", "goodbye").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    autorebase(repo_dir, &options).expect("error autorebasing");
+
+    let conflicts_path = get_git_common_dir(repo_dir)
+        .expect("error getting git dir")
+        .join("autorebase/conflicts.toml");
+    assert!(is_stuck(&conflicts_path, "wip"));
+
+    resolve(
+        repo_dir,
+        "wip",
+        &options,
+        &ResolveOptions {
+            exec: Some(
+                "echo resolved > b.txt && git add b.txt && GIT_EDITOR=true git rebase --continue"
+                    .to_owned(),
+            ),
+            ..Default::default()
+        },
+    )
+    .expect("error resolving");
+
+    print_git_log_graph(repo_dir);
+
+    // `wip` should now be on top of `Third`, the commit that conflicted.
+    let third = get_branch_commit(repo_dir, "master~");
+    let wip_parent = get_branch_commit(repo_dir, "wip~");
+    assert_eq!(wip_parent, third);
+
+    let wip_contents = git(&["show", "wip:b.txt"], repo_dir)
+        .expect("error showing file")
+        .stdout;
+    assert_eq!(String::from_utf8_lossy(&wip_contents).trim(), "resolved");
+
+    assert!(!is_stuck(&conflicts_path, "wip"));
+}
+
+// If the user doesn't finish the rebase it should be aborted and the branch
+// left as it was.
+#[test]
+fn resolve_abandoned() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("WIP").write("a.txt", "goodbye").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let before = get_branch_commit(repo_dir, "wip");
+
+    resolve(
+        repo_dir,
+        "wip",
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+        &ResolveOptions {
+            exec: Some("true".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect_err("resolve should fail if the rebase isn't finished");
+
+    assert_eq!(get_branch_commit(repo_dir, "wip"), before);
+}
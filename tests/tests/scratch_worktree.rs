@@ -0,0 +1,230 @@
+use crate::utils::*;
+use autorebase::{get_git_common_dir, AutorebaseOptions, BranchOutcome};
+use git_commands::git;
+use std::path::Path;
+
+fn scratch_worktree_path(repo_dir: &Path) -> std::path::PathBuf {
+    get_git_common_dir(repo_dir)
+        .expect("error getting git dir")
+        .join("autorebase/autorebase_worktree")
+}
+
+fn assert_rebased(repo_dir: &Path, outcome: &autorebase::RunOutcome) {
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master")
+    );
+    // Files outside the sparse checkout are still rebased correctly.
+    let b = git(&["show", "wip:b/b.txt"], repo_dir).expect("error showing file");
+    assert_eq!(b.stdout, b"world");
+}
+
+#[test]
+fn sparse_checkout_dirs() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a/a.txt", "hello")
+        .write("b/b.txt", "hello")
+        .write("top.txt", "hello")
+        .child(commit("Second").write("a/a.txt", "world").branch("master"))
+        .child(commit("Third").write("b/b.txt", "world").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let outcome = run_autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            sparse_checkout: vec!["a".to_owned()],
+            ..Default::default()
+        },
+    );
+
+    assert_rebased(repo_dir, &outcome);
+
+    let worktree = scratch_worktree_path(repo_dir);
+    assert!(worktree.join("a/a.txt").is_file());
+    assert!(worktree.join("top.txt").is_file());
+    assert!(!worktree.join("b").exists());
+
+    // The main worktree isn't affected.
+    assert!(repo_dir.join("b/b.txt").is_file());
+}
+
+#[test]
+fn sparse_checkout_inherited() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a/a.txt", "hello")
+        .write("b/b.txt", "hello")
+        .write("top.txt", "hello")
+        .child(commit("Second").write("a/a.txt", "world").branch("master"))
+        .child(commit("Third").write("b/b.txt", "world").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    git(&["sparse-checkout", "set", "--cone", "b"], repo_dir).expect("error setting sparse");
+
+    let outcome = run_autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    );
+
+    assert_rebased(repo_dir, &outcome);
+
+    let worktree = scratch_worktree_path(repo_dir);
+    assert!(worktree.join("b/b.txt").is_file());
+    assert!(!worktree.join("a").exists());
+}
+
+// Without `--sparse` the settings follow the worktree autorebase is run from,
+// even if `--sparse` was used before.
+#[test]
+fn sparse_checkout_updated() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a/a.txt", "hello")
+        .write("b/b.txt", "hello")
+        .write("top.txt", "hello")
+        .child(commit("Second").write("a/a.txt", "world").branch("master"))
+        .child(commit("Third").write("b/b.txt", "world").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let autorebase_sparse = |sparse_checkout: &[&str]| {
+        run_autorebase(
+            repo_dir,
+            &AutorebaseOptions {
+                onto_branch: Some("master".to_owned()),
+                sparse_checkout: sparse_checkout.iter().map(|&d| d.to_owned()).collect(),
+                ..Default::default()
+            },
+        );
+    };
+    let worktree = scratch_worktree_path(repo_dir);
+
+    autorebase_sparse(&["a"]);
+    assert!(!worktree.join("b").exists());
+
+    autorebase_sparse(&[]);
+    assert!(worktree.join("a/a.txt").is_file());
+    assert!(worktree.join("b/b.txt").is_file());
+
+    git(&["sparse-checkout", "set", "--cone", "b"], repo_dir).expect("error setting sparse");
+    autorebase_sparse(&[]);
+    assert!(!worktree.join("a").exists());
+    assert!(worktree.join("b/b.txt").is_file());
+}
+
+// LFS files shouldn't be downloaded into the scratch worktree, and the repo's
+// config shouldn't be changed to do that. This uses a fake `git-lfs` that
+// "downloads" files unless `GIT_LFS_SKIP_SMUDGE` is set.
+#[cfg(unix)]
+#[test]
+fn lfs_not_downloaded() {
+    use git_commands::{configure, Settings};
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    struct ResetSettings;
+    impl Drop for ResetSettings {
+        fn drop(&mut self) {
+            configure(Settings::default());
+        }
+    }
+
+    git_fixed_dates();
+
+    let bin_dir = tempfile::tempdir().expect("error creating temporary directory");
+    let git_lfs = bin_dir.path().join("git-lfs");
+    fs::write(
+        &git_lfs,
+        "#!/bin/sh\n\
+         if [ \"$1\" = smudge ] && [ -z \"$GIT_LFS_SKIP_SMUDGE\" ]; then\n\
+         cat > /dev/null; echo downloaded\n\
+         else\n\
+         cat\n\
+         fi\n",
+    )
+    .expect("error writing git-lfs");
+    fs::set_permissions(&git_lfs, fs::Permissions::from_mode(0o755))
+        .expect("error setting permissions");
+    let root = commit("First")
+        .write(".gitattributes", "*.bin filter=lfs\n")
+        .write("big.bin", "pointer")
+        .child(commit("Second").write("a.txt", "hello").branch("master"))
+        .child(commit("Third").write("b.txt", "world").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    git(
+        &["config", "filter.lfs.smudge", "git-lfs smudge -- %f"],
+        repo_dir,
+    )
+    .expect("error setting config");
+    git(
+        &["config", "filter.lfs.clean", "git-lfs clean -- %f"],
+        repo_dir,
+    )
+    .expect("error setting config");
+    let git_common_dir = get_git_common_dir(repo_dir).expect("error getting git dir");
+    let config_before =
+        fs::read_to_string(git_common_dir.join("config")).expect("error reading config");
+
+    // Put the fake `git-lfs` first in the PATH of git commands (and so of
+    // the filters they run). This is only for git commands, and only until
+    // the end of the test, so it doesn't affect other tests.
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let mut paths = vec![bin_dir.path().to_owned()];
+    paths.extend(std::env::split_paths(&path));
+    let path = std::env::join_paths(paths).expect("error joining PATH");
+    configure(Settings {
+        env: vec![(
+            "PATH".to_owned(),
+            path.into_string().expect("PATH is not unicode"),
+        )],
+        ..Default::default()
+    });
+    let _reset = ResetSettings;
+
+    let outcome = run_autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    );
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+
+    let worktree = scratch_worktree_path(repo_dir);
+    let big = fs::read_to_string(worktree.join("big.bin")).expect("error reading file");
+    assert_eq!(big, "pointer");
+
+    let config_after =
+        fs::read_to_string(git_common_dir.join("config")).expect("error reading config");
+    assert_eq!(config_after, config_before);
+    assert!(!git_common_dir.join("config.worktree").exists());
+
+    // It still downloads in the main worktree.
+    fs::remove_file(repo_dir.join("big.bin")).expect("error removing file");
+    git(&["checkout", "--", "big.bin"], repo_dir).expect("error checking out");
+    let big = fs::read_to_string(repo_dir.join("big.bin")).expect("error reading file");
+    assert_eq!(big, "downloaded\n");
+}
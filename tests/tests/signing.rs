@@ -0,0 +1,54 @@
+use crate::utils::*;
+use autorebase::{autorebase, AutorebaseOptions, SigningPolicy};
+use git_commands::git;
+
+// Build a repo where signing is required by the config but can't work because
+// the signing program always fails. Then run autorebase with the given policy
+// and return whether `wip` was rebased.
+fn rebase_with_broken_signing(signing_policy: SigningPolicy) -> bool {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"))
+        .child(commit("WIP").write("b.txt", "foo").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    git(&["config", "commit.gpgsign", "true"], repo_dir).expect("error setting config");
+    git(&["config", "gpg.program", "false"], repo_dir).expect("error setting config");
+
+    let before = get_branch_commit(repo_dir, "wip");
+
+    autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            signing_policy,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    get_branch_commit(repo_dir, "wip") != before
+}
+
+// With the default policy the branch needs signing, so it should be skipped
+// rather than failing the whole run.
+#[test]
+fn signing_unavailable_skips_branch() {
+    assert!(!rebase_with_broken_signing(SigningPolicy::Config));
+    assert!(!rebase_with_broken_signing(SigningPolicy::Always));
+}
+
+// If signing is disabled, or the branch isn't signed, it doesn't matter that
+// signing is broken.
+#[test]
+fn signing_not_needed() {
+    assert!(rebase_with_broken_signing(SigningPolicy::Never));
+    assert!(rebase_with_broken_signing(SigningPolicy::PreserveIfSigned));
+}
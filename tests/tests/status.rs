@@ -0,0 +1,98 @@
+use crate::utils::*;
+use autorebase::{autorebase, status, AutorebaseOptions, SkipReason};
+use git_commands::git;
+
+// Check the status of a repo with a stuck branch, a branch with an upstream
+// and a branch that can be rebased.
+#[test]
+fn status_branches() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second").write("a.txt", "world").child(
+                commit("Third")
+                    .write("b.txt", "and")
+                    .child(commit("Fourth").write("b.txt", "others").branch("master")),
+            ),
+        )
+        .child(commit("WIP").write("b.txt", "goodbye").branch("stuck"))
+        .child(
+            commit("Other")
+                .write("c.txt", "foo")
+                .branch_with_upstream("other_main", "master"),
+        )
+        .child(commit("Fine").write("d.txt", "foo").branch("fine"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    let before = status(repo_dir, &options).expect("error getting status");
+
+    assert_eq!(before.target_branch, "master");
+    let names: Vec<&str> = before.branches.iter().map(|b| b.branch.as_str()).collect();
+    assert_eq!(names, ["fine", "other_main", "stuck"]);
+
+    let fine = &before.branches[0];
+    assert_eq!(fine.behind, Some(3));
+    assert_eq!(fine.ahead, Some(1));
+    assert!(!fine.stuck);
+    assert_eq!(fine.skip_reason, None);
+
+    let other_main = &before.branches[1];
+    assert_eq!(other_main.upstream.as_deref(), Some("master"));
+    assert_eq!(other_main.skip_reason, Some(SkipReason::HasUpstream));
+
+    assert!(!before.branches[2].stuck);
+
+    autorebase(repo_dir, &options).expect("error autorebasing");
+
+    let after = status(repo_dir, &options).expect("error getting status");
+
+    let fine = &after.branches[0];
+    assert_eq!(fine.behind, Some(0));
+
+    // `stuck` got rebased onto `Second` and then stuck.
+    let stuck = &after.branches[2];
+    assert_eq!(stuck.behind, Some(2));
+    assert_eq!(stuck.ahead, Some(1));
+    assert!(stuck.stuck);
+}
+
+// A branch with no history in common with the target has no behind/ahead
+// counts, but it doesn't stop the status from being shown.
+#[test]
+fn status_unrelated_branch() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    git(&["switch", "--orphan", "unrelated"], repo_dir).expect("error switching branch");
+    git(&["commit", "--allow-empty", "-m", "Unrelated"], repo_dir).expect("error committing");
+    git(&["switch", "master"], repo_dir).expect("error switching branch");
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    let repo_status = status(repo_dir, &options).expect("error getting status");
+
+    let unrelated = &repo_status.branches[0];
+    assert_eq!(unrelated.branch, "unrelated");
+    assert_eq!(unrelated.behind, None);
+    assert_eq!(unrelated.ahead, None);
+}
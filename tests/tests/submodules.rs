@@ -0,0 +1,133 @@
+use crate::utils::*;
+use autorebase::{autorebase, AutorebaseOptions, BranchOutcome};
+use git_commands::git;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn run_git(args: &[&str], dir: &Path) {
+    git(args, dir).expect("git failed");
+}
+
+/// Make a repo with a submodule whose commit was changed on `master` and on
+/// `wip`, to a newer commit on `wip`. Returns the submodule's repo, the
+/// superproject and the commit `wip` changed it to.
+fn build_repos() -> (TempDir, TempDir, String) {
+    git_fixed_dates();
+
+    let sub = create_temporary_git_repo();
+    for message in ["One", "Two", "Three"] {
+        run_git(&["commit", "--allow-empty", "-m", message], sub.path());
+    }
+
+    let repo = create_temporary_git_repo();
+    let repo_dir = repo.path();
+    let sub_dir = repo_dir.join("sub");
+    run_git(
+        &[
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            sub.path().to_str().unwrap(),
+            "sub",
+        ],
+        repo_dir,
+    );
+
+    run_git(&["checkout", "master~2"], &sub_dir);
+    run_git(&["commit", "-am", "Base"], repo_dir);
+    run_git(&["branch", "wip"], repo_dir);
+
+    run_git(&["checkout", "master~1"], &sub_dir);
+    run_git(&["commit", "-am", "Update sub"], repo_dir);
+
+    run_git(&["switch", "wip"], repo_dir);
+    run_git(&["checkout", "master"], &sub_dir);
+    run_git(&["commit", "-am", "Update sub more"], repo_dir);
+    let newest = get_branch_commit(&sub_dir, "HEAD");
+
+    run_git(&["switch", "master"], repo_dir);
+    run_git(&["submodule", "update"], repo_dir);
+
+    (sub, repo, newest)
+}
+
+#[test]
+fn submodule_conflict_stuck() {
+    let (_sub, repo, _) = build_repos();
+    let repo_dir = repo.path();
+
+    let wip_before = get_branch_commit(repo_dir, "wip");
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Conflicts)
+    ));
+    assert_eq!(get_branch_commit(repo_dir, "wip"), wip_before);
+}
+
+#[test]
+fn submodule_conflict_resolved() {
+    let (_sub, repo, newest) = build_repos();
+    let repo_dir = repo.path();
+    let sub_dir = repo_dir.join("sub");
+
+    // A branch in the submodule, to check that it is rebased too. The
+    // submodule is a new clone so it doesn't have the config from
+    // `create_temporary_git_repo()`.
+    run_git(&["config", "user.email", "me@example.com"], &sub_dir);
+    run_git(&["config", "user.name", "Me"], &sub_dir);
+    run_git(&["switch", "--create", "feature", "master~2"], &sub_dir);
+    run_git(&["commit", "--allow-empty", "-m", "Feature"], &sub_dir);
+    run_git(&["switch", "--detach", "master"], &sub_dir);
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            resolve_submodules: true,
+            recurse_submodules: true,
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    print_git_log_graph(repo_dir);
+    print_git_log_graph(&sub_dir);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master")
+    );
+    // The newer submodule commit was kept.
+    assert_eq!(get_branch_commit(repo_dir, "wip:sub"), newest);
+
+    assert_eq!(outcome.submodules.len(), 1);
+    let sub_outcome = outcome.submodules[0]
+        .outcome
+        .as_ref()
+        .expect("error autorebasing submodule");
+    assert!(matches!(
+        sub_outcome.outcome("feature"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(&sub_dir, "feature^"),
+        get_branch_commit(&sub_dir, "master")
+    );
+}
@@ -0,0 +1,174 @@
+use crate::utils::*;
+use autorebase::{autorebase, status, AutorebaseOptions, BranchOutcome};
+use git_commands::git;
+
+// Rebase onto a tag instead of the tip of master.
+#[test]
+fn onto_tag() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second")
+                .write("b.txt", "foo")
+                .child(commit("Broken").write("a.txt", "oops").branch("master")),
+        )
+        .child(commit("WIP").write("c.txt", "bar").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    git(&["tag", "-a", "-m", "Release", "v1.0", "master^"], repo_dir).expect("error tagging");
+
+    let outcome = run_autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            onto_commit: "v1.0".parse().unwrap(),
+            ..Default::default()
+        },
+    );
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master^")
+    );
+}
+
+// Rebase onto the newest commit that CI has marked with a note.
+#[test]
+fn onto_noted_commit() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(
+            commit("Second")
+                .write("b.txt", "foo")
+                .child(commit("Broken").write("a.txt", "oops").branch("master")),
+        )
+        .child(commit("WIP").write("c.txt", "bar").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    git(
+        &["notes", "--ref", "ci", "add", "-m", "passed", "master^"],
+        repo_dir,
+    )
+    .expect("error adding note");
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        onto_commit: "notes:ci".parse().unwrap(),
+        ..Default::default()
+    };
+
+    let repo_status = status(repo_dir, &options).expect("error getting status");
+    assert_eq!(
+        repo_status.target_commit,
+        get_branch_commit(repo_dir, "master^")
+    );
+    assert_eq!(repo_status.branches[0].behind, Some(1));
+
+    let outcome = run_autorebase(repo_dir, &options);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master^")
+    );
+
+    // Once the tip is marked too it is used.
+    git(
+        &["notes", "--ref", "ci", "add", "-m", "passed", "master"],
+        repo_dir,
+    )
+    .expect("error adding note");
+
+    let outcome = run_autorebase(repo_dir, &options);
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master")
+    );
+}
+
+// Branches are never moved back to an older target commit.
+#[test]
+fn older_target_commit() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(commit("WIP").write("c.txt", "bar").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let outcome = run_autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            ..Default::default()
+        },
+    );
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    let wip = get_branch_commit(repo_dir, "wip");
+
+    let outcome = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            onto_commit: "master^".parse().unwrap(),
+            ..Default::default()
+        },
+    )
+    .expect("error autorebasing");
+
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::UpToDate)
+    ));
+    assert_eq!(get_branch_commit(repo_dir, "wip"), wip);
+}
+
+#[test]
+fn no_noted_commit() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(commit("WIP").write("c.txt", "bar").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let result = autorebase(
+        repo_dir,
+        &AutorebaseOptions {
+            onto_branch: Some("master".to_owned()),
+            onto_commit: "notes:ci".parse().unwrap(),
+            ..Default::default()
+        },
+    );
+    assert!(result.is_err());
+}
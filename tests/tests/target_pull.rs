@@ -0,0 +1,66 @@
+use crate::utils::*;
+use autorebase::{AutorebaseOptions, BranchOutcome, TargetPull};
+use git_commands::git;
+
+// If the target branch can't be pulled (e.g. because we're offline) branches
+// are still rebased onto the local target branch.
+#[test]
+fn unreachable_remote() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("b.txt", "foo").branch("master"))
+        .child(commit("WIP").write("c.txt", "bar").branch("wip"));
+
+    let repo = build_repo(&root, Some("master"));
+    let repo_dir = repo.path();
+
+    let missing = tempfile::tempdir().expect("error creating temporary directory");
+    let missing_url = missing.path().join("missing");
+    git(
+        &["remote", "add", "origin", missing_url.to_str().unwrap()],
+        repo_dir,
+    )
+    .expect("error adding remote");
+    git(&["config", "branch.master.remote", "origin"], repo_dir).expect("error setting config");
+    git(
+        &["config", "branch.master.merge", "refs/heads/master"],
+        repo_dir,
+    )
+    .expect("error setting config");
+
+    let options = AutorebaseOptions {
+        onto_branch: Some("master".to_owned()),
+        ..Default::default()
+    };
+
+    // Pulled where `master` is checked out.
+    let outcome = run_autorebase(repo_dir, &options);
+    assert!(matches!(outcome.target_pull, TargetPull::Failed(_)));
+    assert!(outcome.target_pull.not_pulled());
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master")
+    );
+
+    // Pulled in our worktree.
+    git(&["switch", "--detach"], repo_dir).expect("error switching");
+    git(&["commit", "--allow-empty", "-m", "Third"], repo_dir).expect("error committing");
+    git(&["branch", "--force", "master"], repo_dir).expect("error moving branch");
+
+    let outcome = run_autorebase(repo_dir, &options);
+    assert!(matches!(outcome.target_pull, TargetPull::Failed(_)));
+    assert!(matches!(
+        outcome.outcome("wip"),
+        Some(BranchOutcome::Rebased)
+    ));
+    assert_eq!(
+        get_branch_commit(repo_dir, "wip^"),
+        get_branch_commit(repo_dir, "master")
+    );
+}
@@ -0,0 +1,66 @@
+use crate::utils::*;
+use autorebase::replay_trace;
+use git_commands::TraceRecord;
+use std::path::Path;
+
+fn record(cwd: &Path, args: &[&str], exit_code: i32) -> TraceRecord {
+    TraceRecord {
+        start_ms: 0,
+        cwd: Some(cwd.to_owned()),
+        args: args.iter().map(|&a| a.to_owned()).collect(),
+        env: Vec::new(),
+        duration_ms: 0,
+        exit_code: Some(exit_code),
+        error: None,
+        stdout: String::new(),
+        stderr: String::new(),
+    }
+}
+
+// Replay a trace that was recorded in a different directory.
+#[test]
+fn replay_mapped_paths() {
+    git_fixed_dates();
+
+    let root = commit("First")
+        .write("a.txt", "hello")
+        .child(commit("Second").write("a.txt", "world").branch("master"));
+
+    let repo = build_repo(&root, Some("master"));
+
+    let repo_dir = repo.path();
+
+    let recorded_dir = Path::new("/home/someone/repo");
+    let path_map = [(recorded_dir.to_owned(), repo_dir.to_owned())];
+
+    let records = [
+        record(recorded_dir, &["rev-parse", "master"], 0),
+        record(recorded_dir, &["rev-parse", "--verify", "missing"], 128),
+        record(
+            recorded_dir,
+            &["-C", "/home/someone/repo/.git", "rev-parse", "--git-dir"],
+            0,
+        ),
+    ];
+    assert_eq!(
+        replay_trace(&records, &path_map).expect("error replaying"),
+        0
+    );
+
+    // Without the path map the directory doesn't exist.
+    assert_eq!(
+        replay_trace(&records[..1], &[]).expect("error replaying"),
+        1
+    );
+
+    // A command that now behaves differently.
+    let records = [record(
+        recorded_dir,
+        &["rev-parse", "--verify", "master"],
+        128,
+    )];
+    assert_eq!(
+        replay_trace(&records, &path_map).expect("error replaying"),
+        1
+    );
+}
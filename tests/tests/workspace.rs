@@ -0,0 +1,79 @@
+use crate::utils::*;
+use autorebase::{autorebase_repos, find_repos, AutorebaseOptions, BranchOutcome};
+use git_commands::git;
+use std::fs;
+
+#[test]
+fn find_repos_in_workspace() {
+    let workspace = tempfile::tempdir().expect("error creating temporary directory");
+    let dir = workspace.path();
+
+    for git_dir in ["a/.git", "a/nested/.git", "group/b/.git", ".hidden/c/.git"] {
+        fs::create_dir_all(dir.join(git_dir)).expect("error creating directory");
+    }
+    // Linked worktrees have a `.git` file.
+    fs::create_dir_all(dir.join("worktree")).expect("error creating directory");
+    fs::write(dir.join("worktree/.git"), "gitdir: elsewhere").expect("error writing file");
+    fs::create_dir_all(dir.join("not_a_repo/src")).expect("error creating directory");
+
+    assert_eq!(
+        find_repos(dir).expect("error finding repos"),
+        [dir.join("a"), dir.join("group/b"), dir.join("worktree")]
+    );
+}
+
+// Rebase two repos with different target branches in parallel.
+#[test]
+fn autorebase_several_repos() {
+    git_fixed_dates();
+
+    let repo_with_target = |target: &str| {
+        let root = commit("First")
+            .write("a.txt", "hello")
+            .child(commit("Second").write("a.txt", "world").branch(target))
+            .child(commit("Third").write("b.txt", "foo").branch("wip"));
+        let repo = build_repo(&root, Some(target));
+        git(&["config", "init.defaultBranch", target], repo.path())
+            .expect("error setting default branch");
+        repo
+    };
+
+    let master_repo = repo_with_target("master");
+    let develop_repo = repo_with_target("develop");
+
+    let outcome = autorebase_repos(
+        &[
+            master_repo.path().to_owned(),
+            develop_repo.path().to_owned(),
+            // This isn't a repo so it should fail without affecting the others.
+            master_repo.path().join("nonexistent"),
+        ],
+        &AutorebaseOptions::default(),
+        2,
+    );
+
+    print_git_log_graph(master_repo.path());
+    print_git_log_graph(develop_repo.path());
+
+    assert!(outcome.has_errors());
+    assert_eq!(outcome.repos.len(), 3);
+    assert!(outcome.repos[2].outcome.is_err());
+
+    let targets: Vec<&str> = outcome
+        .outcomes()
+        .map(|o| o.target_branch.as_str())
+        .collect();
+    assert_eq!(targets, ["master", "develop"]);
+
+    for run in outcome.outcomes() {
+        assert!(matches!(run.outcome("wip"), Some(BranchOutcome::Rebased)));
+    }
+    assert_eq!(
+        get_branch_commit(master_repo.path(), "wip^"),
+        get_branch_commit(master_repo.path(), "master")
+    );
+    assert_eq!(
+        get_branch_commit(develop_repo.path(), "wip^"),
+        get_branch_commit(develop_repo.path(), "develop")
+    );
+}
@@ -1,7 +1,8 @@
 use anyhow::{anyhow, Result};
+use autorebase::{autorebase, AutorebaseOptions, RunOutcome};
 use git_commands::*;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
-use std::fs::{remove_file, write};
+use std::fs::{create_dir_all, remove_file, write};
 use std::path::Path;
 use tempfile::{tempdir, TempDir};
 
@@ -15,6 +16,14 @@ pub fn git_fixed_dates() {
     std::env::set_var("GIT_COMMITTER_DATE", "@0 +0000");
 }
 
+/// Run `autorebase()` in `repo_dir`, print the commit graph afterwards and
+/// return the outcome.
+pub fn run_autorebase(repo_dir: &Path, options: &AutorebaseOptions) -> RunOutcome {
+    let outcome = autorebase(repo_dir, options).expect("error autorebasing");
+    print_git_log_graph(repo_dir);
+    outcome
+}
+
 /// Create a temporary directory and initialise it as a Git repo.
 pub fn create_temporary_git_repo() -> TempDir {
     let repo_dir = tempdir().expect("Couldn't create temporary directory");
@@ -49,6 +58,43 @@ pub fn print_git_log_graph(repo_dir: &Path) {
     println!("\n{}\n", String::from_utf8_lossy(&out));
 }
 
+/// Get the commit hash that `branch` points to.
+pub fn get_branch_commit(repo_dir: &Path, branch: &str) -> String {
+    let out = git(&["rev-parse", branch], repo_dir)
+        .expect("error getting commit")
+        .stdout;
+    String::from_utf8_lossy(&out).trim().to_owned()
+}
+
+/// Git ignores reflog entries with a timestamp of 0, which is what
+/// `git_fixed_dates()` gives us, so move the ones for branches to 2001. Any
+/// others (e.g. written by libgit2, which uses the current time) are moved
+/// there too so that tests don't depend on the backend.
+pub fn fix_reflog_dates(repo_dir: &Path) {
+    let common_dir = git(&["rev-parse", "--git-common-dir"], repo_dir)
+        .expect("error getting git dir")
+        .stdout;
+    let logs_dir = repo_dir
+        .join(String::from_utf8_lossy(&common_dir).trim())
+        .join("logs/refs/heads");
+    for entry in std::fs::read_dir(logs_dir).expect("error reading reflogs") {
+        let path = entry.expect("error reading reflogs").path();
+        let log = std::fs::read_to_string(&path).expect("error reading reflog");
+        // Each line is `<old> <new> <name> <<email>> <time> <zone>\t<message>`.
+        let log: String = log
+            .lines()
+            .map(|line| {
+                let (ident, message) = line.split_once('\t').unwrap_or((line, ""));
+                match ident.rsplitn(3, ' ').collect::<Vec<_>>().as_slice() {
+                    [zone, _time, rest] => format!("{} 1000000000 {}\t{}\n", rest, zone, message),
+                    _ => format!("{}\n", line),
+                }
+            })
+            .collect();
+        write(&path, log).expect("error writing reflog");
+    }
+}
+
 /// A commit description, used to build Git repos.
 #[derive(Default)]
 pub struct CommitDescription {
@@ -140,6 +186,9 @@ pub fn build_repo(root: &CommitDescription, checkout_when_done: Option<&str>) ->
             match change {
                 Some(contents) => {
                     eprintln!("  Write {:?}", path);
+                    if let Some(parent) = path.parent() {
+                        create_dir_all(parent).expect("error creating directory");
+                    }
                     write(&path, contents).expect("error writing file");
                 }
                 None => {